@@ -14,7 +14,7 @@ use gameai::game::ParseGame;
 
 fn do_main(mut conn: TcpStream) {
     loop {
-        let mut buf = [0; 512];
+        let mut buf = [0; 4096];
         println!("Waiting for input.");
         conn.read(&mut buf).unwrap();
         let game: game::connectfour::ConnectFour = deserialize(&buf[..]).unwrap();
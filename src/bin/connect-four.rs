@@ -3,11 +3,15 @@
 
 extern crate gameai;
 extern crate clap;
+extern crate rand;
 
-use clap::{Arg, App, value_t};
+use clap::{Arg, App, value_t, values_t};
+use rand::Rng;
 
 use gameai::game;
 use gameai::runner;
+use gameai::strategies::mcts_core::MemoryLimit;
+use gameai::strategies::mcts_selection::{Policy, Rave};
 
 fn do_main() {
     let matches = App::new("Connect Four")
@@ -23,6 +27,69 @@ fn do_main() {
                 )
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("max_sims")
+                .value_name("UINT")
+                .long("monte_carlo_max_sims")
+                .help(
+                    "Most simulations to run per move, on top of the timeout.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("batch_size")
+                .value_name("UINT")
+                .long("monte_carlo_batch_size")
+                .default_value("1000")
+                .help("Simulations to run between checks of the time and simulation limits.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("policy")
+                .value_name("POLICY")
+                .long("monte_carlo_policy")
+                .default_value("ucb1")
+                .possible_values(&["ucb1", "ucb1-tuned", "puct", "thompson"])
+                .help("How MCTS picks which move to explore.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rave")
+                .value_name("UINT")
+                .long("monte_carlo_rave")
+                .help(
+                    "Blend all-moves-as-first results into MCTS, counting them for half after this many visits.",
+                )
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max_nodes")
+                .value_name("UINT")
+                .long("monte_carlo_max_nodes")
+                .help("Most positions to keep stats for, evicting the least useful past that.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ponder")
+                .long("ponder")
+                .help("Let MCTS keep searching while the opponent thinks."),
+        )
+        .arg(
+            Arg::with_name("transpositions")
+                .long("transpositions")
+                .help("Share tree nodes between positions reached by different move orders."),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .value_name("UINT")
+                .long("seed")
+                .help(
+                    "Seed the searches and who starts, to replay a game exactly. Up to four comma-separated numbers, with any left out taken as 0.",
+                )
+                .takes_value(true)
+                .use_delimiter(true)
+                .max_values(4),
+        )
         .arg(
             Arg::with_name("workers")
                 .short("w")
@@ -59,6 +126,30 @@ fn do_main() {
                 .help("Minimum time in between worker stats flushes.")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("width")
+                .value_name("UINT")
+                .long("width")
+                .default_value("7")
+                .help("Number of columns on the board.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("height")
+                .value_name("UINT")
+                .long("height")
+                .default_value("6")
+                .help("Number of rows on the board.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("needed")
+                .value_name("UINT")
+                .long("needed")
+                .default_value("4")
+                .help("How many in a row it takes to win.")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("merger_queue_bound")
                 .short("i")
@@ -71,6 +162,35 @@ fn do_main() {
         .get_matches();
 
     let timeout = value_t!(matches.value_of("timeout"), u64).unwrap_or_else(|e| e.exit());
+    let max_sims = if matches.is_present("max_sims") {
+        Some(value_t!(matches.value_of("max_sims"), u64).unwrap_or_else(|e| e.exit()))
+    } else {
+        None
+    };
+    let batch_size = value_t!(matches.value_of("batch_size"), u64).unwrap_or_else(|e| e.exit());
+    let policy = value_t!(matches.value_of("policy"), Policy).unwrap_or_else(|e| e.exit());
+    let rave = if matches.is_present("rave") {
+        let k = value_t!(matches.value_of("rave"), u64).unwrap_or_else(|e| e.exit());
+        Some(Rave::Equivalence(k as f64))
+    } else {
+        None
+    };
+    let memory_limit = if matches.is_present("max_nodes") {
+        let n = value_t!(matches.value_of("max_nodes"), usize).unwrap_or_else(|e| e.exit());
+        Some(MemoryLimit::Nodes(n))
+    } else {
+        None
+    };
+    let seed = if matches.is_present("seed") {
+        let words = values_t!(matches.values_of("seed"), u32).unwrap_or_else(|e| e.exit());
+        let mut seed = [0; 4];
+        seed[..words.len()].copy_from_slice(&words);
+        Some(seed)
+    } else {
+        None
+    };
+    let ponder = matches.is_present("ponder");
+    let transpositions = matches.is_present("transpositions");
     let workers = value_t!(matches.value_of("workers"), u64).unwrap_or_else(|e| e.exit());
     let worker_batch_size = value_t!(matches.value_of("worker_batch_size"), u64)
         .unwrap_or_else(|e| e.exit());
@@ -80,9 +200,25 @@ fn do_main() {
         .unwrap_or_else(|e| e.exit());
     let merger_queue_bound = value_t!(matches.value_of("merger_queue_bound"), usize)
         .unwrap_or_else(|e| e.exit());
+    let width = value_t!(matches.value_of("width"), usize).unwrap_or_else(|e| e.exit());
+    let height = value_t!(matches.value_of("height"), usize).unwrap_or_else(|e| e.exit());
+    let needed = value_t!(matches.value_of("needed"), usize).unwrap_or_else(|e| e.exit());
+    if !game::connectfour::ConnectFour::fits(width, height, needed) {
+        clap::Error::with_description(
+            &format!(
+                "Can't play {} in a row on a {}x{} board: every size must be at least 1 and width * (height + 1) at most 64.",
+                needed,
+                width,
+                height
+            ),
+            clap::ErrorKind::InvalidValue,
+        ).exit();
+    }
 
     use game::connectfour::ConnectFour;
     use gameai::strategies::mcts;
+    use gameai::strategies::mcts_ensemble;
+    use gameai::strategies::mcts_tree;
     use gameai::strategies::mcts_parallel;
     use gameai::strategies::mcts_rayon;
     use runner::AIPlayer;
@@ -90,8 +226,13 @@ fn do_main() {
     let mut _pc1 = AIPlayer::<ConnectFour, mcts::MCTS<ConnectFour>>::new(
         "MCTS_AI",
         mcts::MCTSParams {
-            timeout: timeout,
-            c: (2.0 as f64).sqrt(),
+            timeout: Some(timeout),
+            policy: policy,
+            rave: rave,
+            ponder: ponder,
+            memory_limit: memory_limit,
+            seed: seed,
+            ..Default::default()
         },
     );
     use runner::NetworkPlayer;
@@ -100,29 +241,71 @@ fn do_main() {
     let mut _pc2 = AIPlayer::<ConnectFour, mcts_rayon::MCTS<ConnectFour>>::new(
         "RAYON_MCTS_AI",
         mcts_rayon::MCTSParams {
+            timeout: Some(timeout),
+            policy: policy,
+            rave: rave,
+            ponder: ponder,
+            memory_limit: memory_limit,
+            seed: seed,
+            max_sims: max_sims,
+            batch_size: batch_size,
             workers: workers,
-            worker_batch_size: worker_batch_size,
-            merger_batch_size: merger_batch_size,
-            min_flush_interval: min_flush_interval,
-            merger_queue_bound: merger_queue_bound,
-            timeout: timeout,
-            c: (2.0 as f64).sqrt(),
+            ..Default::default()
         },
     );
 
     let mut _pc3 = AIPlayer::<ConnectFour, mcts_parallel::MCTS<ConnectFour>>::new(
-        "RAYON_MCTS_AI",
+        "PARALLEL_MCTS_AI",
         mcts_parallel::MCTSParams {
+            timeout: Some(timeout),
+            policy: policy,
+            rave: rave,
+            ponder: ponder,
+            memory_limit: memory_limit,
+            seed: seed,
             workers: workers,
-            worker_batch_size: worker_batch_size,
+            batch_size: worker_batch_size,
             merger_batch_size: merger_batch_size,
             min_flush_interval: min_flush_interval,
             merger_queue_bound: merger_queue_bound,
-            timeout: timeout,
-            c: (2.0 as f64).sqrt(),
+            ..Default::default()
+        },
+    );
+    let mut _pc4 = AIPlayer::<ConnectFour, mcts_ensemble::MCTS<ConnectFour>>::new(
+        "ENSEMBLE_MCTS_AI",
+        mcts_ensemble::MCTSParams {
+            timeout: Some(timeout),
+            policy: policy,
+            rave: rave,
+            ponder: ponder,
+            memory_limit: memory_limit,
+            seed: seed,
+            max_sims: max_sims,
+            batch_size: batch_size,
+            workers: workers,
+            ..Default::default()
+        },
+    );
+    let mut _pc5 = AIPlayer::<ConnectFour, mcts_tree::MCTS<ConnectFour>>::new(
+        "TREE_MCTS_AI",
+        mcts_tree::MCTSParams {
+            timeout: Some(timeout),
+            policy: policy,
+            rave: rave,
+            ponder: ponder,
+            memory_limit: memory_limit,
+            seed: seed,
+            max_sims: max_sims,
+            batch_size: batch_size,
+            transpositions: transpositions,
+            ..Default::default()
         },
     );
-    runner::Runner::run(&mut _pc2, &mut _human);
+    let first = game::seeded_rng(seed, 0).gen();
+    let board = ConnectFour::with_dimensions(&first, width, height, needed);
+    let mut runner = runner::Runner::new_with_board(board, &mut _pc2, &mut _human);
+    runner.set_pondering(ponder);
+    runner.play();
 }
 
 fn main() {
@@ -9,8 +9,8 @@ fn main() {
     let mut human2 = AIPlayer::<Dots, MCTS<Dots>>::new(
         "Robot",
         MCTSParams {
-            timeout: 10000,
-            c: (2.0 as f64).sqrt(),
+            timeout: Some(10000),
+            ..Default::default()
         },
     );
     Runner::run(&mut human1, &mut human2);
@@ -12,14 +12,12 @@ use gameai::game::Game;
 
 fn do_profile() {
     PROFILER.lock().unwrap().start("./negamax.profile");
-    let mut strategy = mcts_rayon::MCTS::create(mcts_rayon::MCTSParams {
+    let mut strategy = mcts_rayon::MCTS::<TrivialGame>::create(mcts_rayon::MCTSParams {
+        timeout: None,
+        max_sims: Some(240000),
+        batch_size: 120000,
         workers: 1,
-        worker_batch_size: 1,
-        merger_batch_size: 1,
-        min_flush_interval: 1,
-        merger_queue_bound: 1,
-        timeout: 1,
-        c: (2.0 as f64).sqrt(),
+        ..Default::default()
     });
     debug(&strategy.decide(&TrivialGame::new(&Player::A)));
     PROFILER.lock().unwrap().stop();
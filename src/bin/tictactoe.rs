@@ -88,20 +88,20 @@ fn do_main() {
     let mut _pc1 = AIPlayer::<TicTacToe, mcts::MCTS<TicTacToe>>::new(
         "MCTS_AI",
         mcts::MCTSParams {
-            timeout: timeout,
-            c: (2.0 as f64).sqrt(),
+            timeout: Some(timeout),
+            ..Default::default()
         },
     );
     let mut _pc2 = AIPlayer::<TicTacToe, mcts_parallel::MCTS<TicTacToe>>::new(
         "PAR_MCTS_AI",
         mcts_parallel::MCTSParams {
+            timeout: Some(timeout),
             workers: workers,
-            worker_batch_size: worker_batch_size,
+            batch_size: worker_batch_size,
             merger_batch_size: merger_batch_size,
             min_flush_interval: min_flush_interval,
             merger_queue_bound: merger_queue_bound,
-            timeout: timeout,
-            c: (2.0 as f64).sqrt(),
+            ..Default::default()
         },
     );
     runner::Runner::run(&mut _human, &mut _pc2);
@@ -90,8 +90,8 @@ fn do_main() {
     let mut _pc1 = AIPlayer::<TrivialGame, mcts::MCTS<TrivialGame>>::new(
         "MCTS_AI",
         mcts::MCTSParams {
-            timeout: timeout,
-            c: (2.0 as f64).sqrt(),
+            timeout: Some(timeout),
+            ..Default::default()
         },
     );
     use runner::NetworkPlayer;
@@ -100,26 +100,23 @@ fn do_main() {
     let mut _pc2 = AIPlayer::<TrivialGame, mcts_rayon::MCTS<TrivialGame>>::new(
         "RAYON_MCTS_AI",
         mcts_rayon::MCTSParams {
+            timeout: Some(timeout),
             workers: workers,
-            worker_batch_size: worker_batch_size,
-            merger_batch_size: merger_batch_size,
-            min_flush_interval: min_flush_interval,
-            merger_queue_bound: merger_queue_bound,
-            timeout: timeout,
-            c: (2.0 as f64).sqrt(),
+            batch_size: 1000,
+            ..Default::default()
         },
     );
 
     let mut _pc3 = AIPlayer::<TrivialGame, mcts_parallel::MCTS<TrivialGame>>::new(
         "PARALLEL_MCTS_AI",
         mcts_parallel::MCTSParams {
+            timeout: Some(timeout),
             workers: workers,
-            worker_batch_size: worker_batch_size,
+            batch_size: worker_batch_size,
             merger_batch_size: merger_batch_size,
             min_flush_interval: min_flush_interval,
             merger_queue_bound: merger_queue_bound,
-            timeout: timeout,
-            c: (2.0 as f64).sqrt(),
+            ..Default::default()
         },
     );
     runner::Runner::run(&mut _pc2, &mut _human);
@@ -1,14 +1,17 @@
 use self::Color::*;
 use self::Slot::*;
 use rand;
+use std::cmp;
 use std::fmt;
 use std::clone::Clone;
 use super::*;
 use std::str::FromStr;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
 
-const HEIGHT: usize = 6;
-const WIDTH: usize = 7;
-const NEEDED: usize = 4;
+// Dimensions of the standard board, used by `Game::new`.
+pub const HEIGHT: usize = 6;
+pub const WIDTH: usize = 7;
+pub const NEEDED: usize = 4;
 
 
 #[derive(Serialize, Deserialize, Clone, Copy, PartialOrd, PartialEq, Hash, Debug, Ord, Eq)]
@@ -77,50 +80,179 @@ impl fmt::Display for Slot {
     }
 }
 
-#[derive(Serialize, Deserialize, Hash, Debug, PartialEq, Eq)]
+/// Two bitboards, one per color.  Cells are numbered column by column from
+/// the bottom, with a spare always-empty bit on top of each column so that
+/// shifted lines can't wrap from one column into the next.  That limits
+/// boards to `width * (height + 1) <= 64`.
+#[derive(Clone, Copy, Hash, Debug, PartialEq, Eq)]
 struct C4Board {
-    board: [[Slot; WIDTH]; HEIGHT],
+    red: u64,
+    black: u64,
+    width: usize,
+    height: usize,
+    needed: usize,
 }
-impl Clone for C4Board {
-    fn clone(&self) -> C4Board {
-        C4Board { board: self.board }
-    }
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "C4Board")]
+struct C4BoardRepr {
+    width: usize,
+    height: usize,
+    needed: usize,
+    board: Vec<Vec<Slot>>,
+}
+
+fn shr(bits: u64, n: usize) -> u64 {
+    bits.checked_shr(n as u32).unwrap_or(0)
+}
+
+fn fits(width: usize, height: usize, needed: usize) -> bool {
+    width > 0 && height > 0 && needed > 0 && width * (height + 1) <= 64
 }
 
 impl C4Board {
+    fn new(width: usize, height: usize, needed: usize) -> Self {
+        assert!(
+            fits(width, height, needed),
+            "A {}x{} board doesn't fit in a bitboard.",
+            width,
+            height
+        );
+        C4Board {
+            red: 0,
+            black: 0,
+            width: width,
+            height: height,
+            needed: needed,
+        }
+    }
+
+    fn bit(&self, i: usize, j: usize) -> u64 {
+        1 << (j * (self.height + 1) + i)
+    }
+
+    fn column_mask(&self, j: usize) -> u64 {
+        ((1 << self.height) - 1) << (j * (self.height + 1))
+    }
+
+    fn pieces(&self, c: Color) -> u64 {
+        match c {
+            R => self.red,
+            B => self.black,
+        }
+    }
+
+    fn filled(&self) -> u64 {
+        self.red | self.black
+    }
+
     fn get(&self, i: usize, j: usize) -> Slot {
-        self.board[i][j]
+        let b = self.bit(i, j);
+        if self.red & b != 0 {
+            Full(R)
+        } else if self.black & b != 0 {
+            Full(B)
+        } else {
+            Empty
+        }
     }
 
     fn num_pieces(&self) -> usize {
-        self.board
-            .iter()
-            .flat_map(|row| row.iter())
-            .map(|s| match s {
-                &Empty => 0,
-                _ => 1,
-            })
-            .sum()
+        self.filled().count_ones() as usize
+    }
+
+    fn column_height(&self, j: usize) -> usize {
+        (self.filled() & self.column_mask(j)).count_ones() as usize
     }
 
     fn set(&mut self, i: usize, j: usize, c: Color) {
-        (*self).board[i][j] = Slot::Full(c);
+        let b = self.bit(i, j);
+        match c {
+            R => self.red |= b,
+            B => self.black |= b,
+        }
+    }
+
+    fn clear(&mut self, i: usize, j: usize) {
+        let b = !self.bit(i, j);
+        self.red &= b;
+        self.black &= b;
+    }
+
+    fn has_line(&self, c: Color) -> bool {
+        let bits = self.pieces(c);
+        let height = self.height;
+        // Vertical, horizontal and the two diagonals.
+        for &shift in &[1, height + 1, height, height + 2] {
+            let mut line = bits;
+            for k in 1..self.needed {
+                line &= shr(bits, k * shift);
+            }
+            if line != 0 {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn to_repr(&self) -> C4BoardRepr {
+        let board = (0..self.height)
+            .map(|i| (0..self.width).map(|j| self.get(i, j)).collect())
+            .collect();
+        C4BoardRepr {
+            width: self.width,
+            height: self.height,
+            needed: self.needed,
+            board: board,
+        }
+    }
+
+    fn from_repr(repr: &C4BoardRepr) -> Option<Self> {
+        if !fits(repr.width, repr.height, repr.needed) || repr.board.len() != repr.height ||
+            repr.board.iter().any(|row| row.len() != repr.width)
+        {
+            return None;
+        }
+
+        let mut new = C4Board::new(repr.width, repr.height, repr.needed);
+        for (i, row) in repr.board.iter().enumerate() {
+            for (j, slot) in row.iter().enumerate() {
+                if let Full(c) = *slot {
+                    new.set(i, j, c);
+                }
+            }
+        }
+        Some(new)
+    }
+}
+
+impl Serialize for C4Board {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_repr().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for C4Board {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+        let repr = C4BoardRepr::deserialize(deserializer)?;
+        C4Board::from_repr(&repr).ok_or_else(|| D::Error::custom("invalid board dimensions"))
     }
 }
 
 impl fmt::Display for C4Board {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let dashes: String = (0..WIDTH * 3).map(|_| "-").collect();
-        for row in self.board.iter().rev() {
+        let dashes: String = (0..self.width * 3).map(|_| "-").collect();
+        for i in (0..self.height).rev() {
             write!(f, "|")?;
-            for slot in row.iter() {
-                write!(f, " {} ", slot)?;
+            for j in 0..self.width {
+                write!(f, " {} ", self.get(i, j))?;
             }
             writeln!(f, "|")?;
         }
         writeln!(f, "|{}|", dashes.as_str())?;
         write!(f, " ")?;
-        for i in 0..WIDTH {
+        for i in 0..self.width {
             write!(f, " {} ", i + 1)?;
         }
         writeln!(f, " ")
@@ -135,6 +267,44 @@ pub struct ConnectFour {
     winner: Option<Color>,
 }
 
+impl ConnectFour {
+    /// Whether `with_dimensions` can make a board this size.
+    pub fn fits(width: usize, height: usize, needed: usize) -> bool {
+        fits(width, height, needed)
+    }
+
+    /// A board of any size whose columns fit in a bitboard, i.e.
+    /// `width * (height + 1) <= 64`, won by `needed` in a row.
+    pub fn with_dimensions(start: &Color, width: usize, height: usize, needed: usize) -> Self {
+        ConnectFour {
+            to_act: *start,
+            ref_player: *start,
+            state: C4Board::new(width, height, needed),
+            winner: None,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.state.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.state.height
+    }
+
+    pub fn needed(&self) -> usize {
+        self.state.needed
+    }
+
+    /// The piece at `row` (counting up from the bottom) and `col`, if any.
+    pub fn get(&self, row: usize, col: usize) -> Option<Color> {
+        match self.state.get(row, col) {
+            Empty => None,
+            Full(c) => Some(c),
+        }
+    }
+}
+
 impl fmt::Display for ConnectFour {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "Acting: {}, Ref: {}", self.to_act, self.ref_player)?;
@@ -144,30 +314,50 @@ impl fmt::Display for ConnectFour {
 
 impl ParseGame for ConnectFour {
     fn parse_move(&self, input: &str) -> Option<Self::Move> {
-        usize::from_str(input).ok().map(|n| (n - 1, self.to_act()))
+        usize::from_str(input)
+            .ok()
+            .and_then(|n| if n >= 1 && n <= self.width() {
+                Some((n - 1, self.to_act()))
+            } else {
+                None
+            })
     }
 }
 
 impl RandGame for ConnectFour {
-    fn random_move<R: rand::Rng>(&mut self, rng: &mut R) -> Option<ValidMoveMut<Self>> {
-        let mut is = [0; WIDTH];
+    fn choose_move<R: rand::Rng>(&self, rng: &mut R, _: &mut Vec<Self::Move>) -> Option<Self::Move> {
+        // Boards are at most 64 columns wide, so this needs no buffer.
+        let mut is = [0; 64];
+        let is = &mut is[..self.width()];
         for (i, r) in is.iter_mut().enumerate() {
             *r = i;
         }
-        rng.shuffle(&mut is);
+        rng.shuffle(is);
 
         let to_act = self.to_act();
-        for &i in &is {
+        for &i in is.iter() {
             let m = (i, to_act);
             if self.move_valid(&m) {
-                return Some(ValidMoveMut {
-                    valid_move: m,
-                    valid_for: self,
-                });
+                return Some(m);
             }
         }
         None
     }
+
+    fn move_prior(&self, &(n, _): &Self::Move) -> f64 {
+        // Columns nearer the middle take part in more lines.
+        (1 + cmp::min(n, self.width() - 1 - n)) as f64
+    }
+}
+
+impl ReversibleGame for ConnectFour {
+    fn undo(&mut self, (n, color): Self::Move) {
+        let i = self.state.column_height(n);
+        assert!(i > 0, "Undoing a move in an empty column.");
+        self.state.clear(i - 1, n);
+        self.winner = None;
+        self.to_act = color;
+    }
 }
 
 impl Game for ConnectFour {
@@ -175,7 +365,15 @@ impl Game for ConnectFour {
     type Agent = Color;
 
     fn reachable(&self, g: &Self) -> bool {
-        g.state.num_pieces() > self.state.num_pieces()
+        let (a, b) = (&self.state, &g.state);
+        if (a.width, a.height, a.needed) != (b.width, b.height, b.needed) {
+            return false;
+        }
+        if a.num_pieces() == b.num_pieces() || self.winner.is_some() {
+            return self == g;
+        }
+        // Pieces never move once played.
+        a.red & !b.red == 0 && a.black & !b.black == 0
     }
 
     fn agent_id(&self, &a: &Self::Agent) -> u32 {
@@ -189,6 +387,17 @@ impl Game for ConnectFour {
         self.winner
     }
 
+    fn outcome(&self) -> Outcome<Self::Agent> {
+        match self.winner {
+            Some(w) => Outcome::Win(w),
+            None => if self.state.num_pieces() == self.state.width * self.state.height {
+                Outcome::Draw
+            } else {
+                Outcome::Ongoing
+            },
+        }
+    }
+
     fn ref_player(&self) -> Self::Agent {
         self.ref_player
     }
@@ -198,25 +407,19 @@ impl Game for ConnectFour {
         let state = &self.state;
         let (col, color) = m;
 
-        if col >= WIDTH || color != self.to_act {
+        if col >= state.width || color != self.to_act {
             return false;
         }
 
-        if let Slot::Full(_) = state.board[HEIGHT - 1][col] {
+        if state.column_height(col) == state.height {
             return false;
         }
 
         true
     }
 
-    fn new(&start: &Self::Agent) -> Self {
-        let board = C4Board { board: [[Slot::new(); WIDTH]; HEIGHT] };
-        ConnectFour {
-            to_act: start,
-            ref_player: start,
-            state: board,
-            winner: None,
-        }
+    fn new(start: &Self::Agent) -> Self {
+        ConnectFour::with_dimensions(start, WIDTH, HEIGHT, NEEDED)
     }
 
     fn to_act(&self) -> Self::Agent {
@@ -231,89 +434,112 @@ impl Game for ConnectFour {
         -1
     }
 
-    fn possible_moves(&self) -> Vec<ValidMove<Self>> {
-        let mut moves = vec![];
+    fn legal_moves(&self, moves: &mut Vec<Self::Move>) {
         let color = self.to_act();
+        moves.clear();
 
         if self.has_winner() {
-            return moves;
+            return;
         }
 
-        for j in 0..WIDTH {
-            if let Slot::Empty = self.state.get(HEIGHT - 1, j) {
-                moves.push(ValidMove {
-                    valid_move: (j, color),
-                    valid_for: self.clone(),
-                })
+        for j in 0..self.state.width {
+            if self.state.column_height(j) < self.state.height {
+                moves.push((j, color));
             }
         }
-        moves
     }
 
 
     fn apply(&mut self, m: Self::Move) {
         let (n, color) = m;
-        for i in 0..HEIGHT {
-            match self.state.board[i][n] {
-                Full(_) => (),
-                Empty => {
-                    self.state.set(i, n, color);
-                    if self.has_won(&color) {
-                        self.winner = Some(color);
-                    }
-                    self.to_act = self.to_act.flip();
-                    return;
-                }
-            }
+        let i = self.state.column_height(n);
+        if i >= self.state.height {
+            panic!("This shouldn't happen for validated moves.");
         }
-        panic!("This shouldn't happen for validated moves.");
+
+        self.state.set(i, n, color);
+        if self.has_won(&color) {
+            self.winner = Some(color);
+        }
+        self.to_act = self.to_act.flip();
     }
 
     fn has_won(&self, &color: &Color) -> bool {
-        // horizontalCheck
-        let color = Slot::Full(color);
-        let get = |i, j| self.state.get(j, i);
-
-        for j in 0..HEIGHT - (NEEDED - 1) {
-            for i in 0..WIDTH {
-                if get(i, j) == color && get(i, j + 1) == color && get(i, j + 2) == color &&
-                    get(i, j + 3) == color
-                {
-                    return true;
-                }
-            }
-        }
-        // verticalCheck
-        for i in 0..WIDTH - (NEEDED - 1) {
-            for j in 0..HEIGHT {
-                if get(i, j) == color && get(i + 1, j) == color && get(i + 2, j) == color &&
-                    get(i + 3, j) == color
-                {
-                    return true;
-                }
-            }
-        }
+        self.state.has_line(color)
+    }
+}
 
-        // ascending diagonal check
-        for i in 3..WIDTH {
-            for j in 0..HEIGHT - (NEEDED - 1) {
-                if get(i, j) == color && get(i - 1, j + 1) == color &&
-                    get(i - 2, j + 2) == color && get(i - 3, j + 3) == color
-                {
-                    return true;
-                }
-            }
-        }
-        // // descendingDiagonalCheck
-        for i in 3..WIDTH {
-            for j in 3..HEIGHT {
-                if get(i, j) == color && get(i - 1, j - 1) == color &&
-                    get(i - 2, j - 2) == color && get(i - 3, j - 3) == color
-                {
-                    return true;
-                }
-            }
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bincode::{deserialize, serialize, Infinite};
+    use strategies::mcts_core::test::play_moves;
+
+    fn play(moves: &[usize]) -> ConnectFour {
+        play_moves(&ConnectFour::new(&R), moves)
+    }
+
+    #[test]
+    fn test_lines() {
+        // Vertical, horizontal, ascending and descending diagonals for R.
+        assert_eq!(play(&[0, 1, 0, 1, 0, 1, 0]).winner(), Some(R));
+        assert_eq!(play(&[2, 2, 3, 3, 4, 4, 5]).winner(), Some(R));
+        assert_eq!(play(&[0, 1, 1, 2, 2, 3, 2, 3, 3, 6, 3]).winner(), Some(R));
+        assert_eq!(play(&[6, 5, 5, 4, 4, 3, 4, 3, 3, 0, 3]).winner(), Some(R));
+        // The top of one column and the bottom of the next aren't a line.
+        assert_eq!(play(&[1, 0, 6, 0, 6, 0, 0, 5, 0, 5, 0]).winner(), None);
+    }
+
+    #[test]
+    fn test_other_dimensions() {
+        let board = ConnectFour::with_dimensions(&R, 9, 6, 5);
+        let mut game = play_moves(&board, &[0, 0, 1, 1, 2, 2, 3, 3]);
+        // Four in a row isn't enough for connect five.
+        assert_eq!(game.winner(), None);
+        assert!(game.try_move((4, R)));
+        assert_eq!(game.winner(), Some(R));
+
+        assert!(ConnectFour::fits(8, 7, 4));
+        assert!(!ConnectFour::fits(8, 8, 4));
+        assert!(!ConnectFour::fits(7, 6, 0));
+        let game = play_moves(&ConnectFour::with_dimensions(&R, 8, 7, 4), &[7; 7]);
+        assert!(!game.move_valid(&(7, game.to_act())));
+        assert_eq!(game.parse_move("8"), Some((7, game.to_act())));
+        assert_eq!(game.parse_move("9"), None);
+    }
+
+    #[test]
+    fn test_reachable() {
+        let root = play(&[3, 4]);
+        assert!(root.reachable(&root));
+        assert!(root.reachable(&play(&[3, 4, 2, 2])));
+        // Transpositions of the same moves count too.
+        assert!(root.reachable(&play(&[2, 4, 3, 2])));
+        assert!(!root.reachable(&play(&[4, 3, 2, 2])));
+        assert!(!root.reachable(&play(&[3])));
+        assert!(!root.reachable(&play(&[3, 5])));
+    }
+
+    #[test]
+    fn test_undo() {
+        let before = play(&[3, 4, 3, 4, 3, 4]);
+        let mut game = before.clone();
+        assert!(game.try_move((3, R)));
+        assert_eq!(game.winner(), Some(R));
+
+        game.undo((3, R));
+        assert_eq!(game, before);
+        assert_eq!(game.winner(), None);
+        assert_eq!(game.to_act(), R);
+    }
+
+    #[test]
+    fn test_serialization() {
+        for game in vec![play(&[3, 3, 4, 2, 6]), ConnectFour::with_dimensions(&B, 9, 6, 5)] {
+            let bytes = serialize(&game, Infinite).unwrap();
+            let back: ConnectFour = deserialize(&bytes[..]).unwrap();
+            assert_eq!(back, game);
+            assert_eq!(format!("{}", back), format!("{}", game));
         }
-        false
     }
 }
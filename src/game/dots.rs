@@ -3,7 +3,7 @@ use rand;
 use std::fmt;
 use super::*;
 
-use std::collections::HashSet;
+use std::collections::BTreeSet;
 use std::hash::Hash;
 use std::hash::Hasher;
 use std::cmp::{Ord, Ordering};
@@ -115,7 +115,7 @@ pub struct Dots {
     ref_player: DotsPlayer,
     winner: Option<DotsPlayer>,
     scores: (usize, usize),
-    possible_moves: HashSet<DotsMove>,
+    possible_moves: BTreeSet<DotsMove>,
 }
 
 impl Eq for Dots {}
@@ -189,9 +189,48 @@ impl ParseGame for Dots {
     }
 }
 
+impl ReversibleGame for Dots {
+    fn undo(&mut self, m: Self::Move) {
+        // With `m` the last edge drawn, every box it borders that is now
+        // complete was completed by it.
+        let completed = self.completes(m);
+        let mover = match completed.first() {
+            Some(&(j, i)) => self.board.owners[j][i].expect("Completed box has no owner."),
+            None => self.to_act.flip(),
+        };
+
+        for (j, i) in completed {
+            (*self).board.owners[j][i] = None;
+            (*self).dec_score(mover);
+        }
+
+        match m {
+            H(j, i) => (*self).board.horizontals[j][i] = false,
+            V(j, i) => (*self).board.verticals[j][i] = false,
+        }
+
+        self.possible_moves.insert(m);
+        self.winner = None;
+        self.to_act = mover;
+    }
+}
+
 impl Game for Dots {
     type Agent = DotsPlayer;
     type Move = DotsMove;
+
+    fn reachable(&self, g: &Self) -> bool {
+        if self.possible_moves.len() == g.possible_moves.len() {
+            return self == g;
+        }
+        // Edges stay drawn and boxes keep their owners.
+        let (a, b) = (&self.board, &g.board);
+        self.possible_moves.len() > g.possible_moves.len()
+            && self.possible_moves.is_superset(&g.possible_moves)
+            && a.owners.iter().zip(b.owners.iter()).all(|(r1, r2)| {
+                r1.iter().zip(r2.iter()).all(|(o1, o2)| o1.is_none() || o1 == o2)
+            })
+    }
     fn to_act(&self) -> Self::Agent {
         self.to_act
     }
@@ -231,7 +270,7 @@ impl Game for Dots {
             (*self).inc_score(acting);
         }
 
-        if self.possible_moves().is_empty() {
+        if self.possible_moves.is_empty() {
             let other = acting.flip();
             match self.get_score(acting).cmp(&self.get_score(other)) {
                 Ordering::Greater => (*self).winner = Some(acting),
@@ -254,6 +293,17 @@ impl Game for Dots {
         self.winner
     }
 
+    fn outcome(&self) -> Outcome<Self::Agent> {
+        match self.winner {
+            Some(w) => Outcome::Win(w),
+            None => if self.possible_moves.is_empty() {
+                Outcome::Draw
+            } else {
+                Outcome::Ongoing
+            },
+        }
+    }
+
     fn agent_id(&self, &agent: &Self::Agent) -> u32 {
         match agent {
             DotsPlayer::A => 0,
@@ -265,7 +315,7 @@ impl Game for Dots {
         self.ref_player
     }
     fn new(&start: &Self::Agent) -> Self {
-        let mut moves = HashSet::new();
+        let mut moves = BTreeSet::new();
 
         for j in 0..HEIGHT {
             for i in 0..WIDTH - 1 {
@@ -295,17 +345,9 @@ impl Game for Dots {
 
     }
 
-    fn possible_moves(&self) -> Vec<ValidMove<Self>> {
-        self.possible_moves
-            .iter()
-            .map(|&m| {
-                let this = self.clone();
-                ValidMove {
-                    valid_move: m,
-                    valid_for: this,
-                }
-            })
-            .collect()
+    fn legal_moves(&self, moves: &mut Vec<Self::Move>) {
+        moves.clear();
+        moves.extend(self.possible_moves.iter().cloned());
     }
 }
 
@@ -381,16 +423,73 @@ impl Dots {
         };
         *it += 1;
     }
+    fn dec_score(&mut self, a: DotsPlayer) {
+        let it = match a {
+            DotsPlayer::A => &mut self.scores.0,
+            DotsPlayer::B => &mut self.scores.1,
+        };
+        *it -= 1;
+    }
 }
 
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use rand::{SeedableRng, XorShiftRng};
 
     #[test]
     fn test_draw_hrow() {
 
         println!("{}", test_board());
     }
+
+    #[test]
+    fn test_reachable() {
+        let mut root = Dots::new(&DotsPlayer::A);
+        root.try_moves(vec![H(0, 0), V(0, 0), H(1, 0)].into_iter());
+        assert!(root.reachable(&root));
+
+        // B takes the box with V(0, 1) and keeps it from then on.
+        let mut taken = root.clone();
+        taken.try_move(V(0, 1));
+        assert!(root.reachable(&taken));
+        let mut later = taken.clone();
+        later.try_move(H(0, 1));
+        assert!(taken.reachable(&later));
+
+        // Lines can't be erased, nor the box go to A instead.
+        assert!(!later.reachable(&root));
+        let mut other = Dots::new(&DotsPlayer::B);
+        other.try_moves(vec![H(0, 0), V(0, 0), H(1, 0), V(0, 1), H(0, 1)].into_iter());
+        assert!(root.reachable(&other));
+        assert!(!taken.reachable(&other));
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        let mut game = Dots::new(&DotsPlayer::A);
+        let mut history = Vec::new();
+        loop {
+            let before = game.clone();
+            let m = match game.random_move(&mut rng) {
+                Some(vm) => {
+                    let m = vm.valid_move;
+                    vm.apply();
+                    m
+                }
+                None => break,
+            };
+            history.push((m, before));
+        }
+
+        while let Some((m, before)) = history.pop() {
+            game.undo(m);
+            assert_eq!(game, before);
+            assert_eq!(format!("{}", game), format!("{}", before));
+            assert_eq!(game.winner(), before.winner());
+            assert_eq!(game.possible_moves.len(), before.possible_moves.len());
+        }
+    }
 }
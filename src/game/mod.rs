@@ -38,45 +38,162 @@ impl<'gs, G: Game> ValidMoveMut<'gs, G> {
 
 pub type Score = i32;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Outcome<A> {
+    Win(A),
+    Draw,
+    Ongoing,
+}
+
+/// A result from the point of view of one player, worst first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Value {
+    Loss,
+    Draw,
+    Win,
+}
+
+impl Value {
+    /// `outcome` as seen by `acting`.
+    pub fn of<A: PartialEq>(outcome: Outcome<A>, acting: A) -> Self {
+        match outcome {
+            Outcome::Win(w) => if w == acting {
+                Value::Win
+            } else {
+                Value::Loss
+            },
+            _ => Value::Draw,
+        }
+    }
+
+    /// The same result for the other player.
+    pub fn flipped(self) -> Self {
+        match self {
+            Value::Win => Value::Loss,
+            Value::Loss => Value::Win,
+            Value::Draw => Value::Draw,
+        }
+    }
+
+    /// This result, which is `seen_by`'s, from `acting`'s point of view.
+    pub fn seen_by<A: PartialEq>(self, seen_by: A, acting: A) -> Self {
+        if seen_by == acting {
+            self
+        } else {
+            self.flipped()
+        }
+    }
+}
+
 pub trait ParseGame: Game {
     fn parse_move(&self, &str) -> Option<Self::Move>;
 }
 
-use rand::Rng;
+use rand::{self, Rng, SeedableRng, XorShiftRng};
+
+/// A generator for random choices.  With a seed, the generator for a given
+/// `stream` is always the same and different streams get unrelated ones, so
+/// every thread of a search can have its own.  Without, it's seeded at
+/// random.
+pub fn seeded_rng(seed: Option<[u32; 4]>, stream: u64) -> XorShiftRng {
+    // MurmurHash3's finalizer, to spread small differences over every bit.
+    fn mix(mut x: u32) -> u32 {
+        x = (x ^ (x >> 16)).wrapping_mul(0x85eb_ca6b);
+        x = (x ^ (x >> 13)).wrapping_mul(0xc2b2_ae35);
+        x ^ (x >> 16)
+    }
+    let seed = match seed {
+        Some(seed) => {
+            let (lo, hi) = (stream as u32, (stream >> 32) as u32);
+            let mut words = [0; 4];
+            for i in 0..4 {
+                let salt = mix(lo ^ (i as u32).wrapping_mul(0x9e37_79b9)) ^ hi.rotate_left(8 * i as u32);
+                words[i] = mix(seed[i] ^ salt);
+            }
+            // The generator can't start from all zeros.
+            if words == [0; 4] {
+                words[0] = 1;
+            }
+            words
+        }
+        None => rand::random(),
+    };
+    SeedableRng::from_seed(seed)
+}
+
 pub trait RandGame: Game + Clone {
+    /// Picks a legal move uniformly at random, using `buf` as scratch space
+    /// so that repeated calls don't allocate.
+    fn choose_move<R: Rng>(&self, rng: &mut R, buf: &mut Vec<Self::Move>) -> Option<Self::Move> {
+        self.legal_moves(buf);
+        if buf.is_empty() {
+            None
+        } else {
+            Some(buf[rng.gen_range(0, buf.len())])
+        }
+    }
+
+    /// How promising `m` looks before any search, as a positive weight
+    /// relative to the other legal moves.  Searches that use priors
+    /// normalise these over the moves available.
+    fn move_prior(&self, _m: &Self::Move) -> f64 {
+        1.0
+    }
+
     fn random_move<R: Rng>(&mut self, rng: &mut R) -> Option<ValidMoveMut<Self>> {
-        let mut moves = self.possible_moves();
-        rng.shuffle(&mut moves);
-        moves.into_iter().next().map(move |m| {
+        let mut buf = Vec::new();
+        self.choose_move(rng, &mut buf).map(move |m| {
             ValidMoveMut {
-                valid_move: m.valid_move,
+                valid_move: m,
                 valid_for: self,
             }
         })
     }
 
-    fn random_outcome<R: Rng>(mut self, rng: &mut R) -> Option<Self::Agent> {
-
-        while !self.has_winner() {
-            match self.random_move(rng) {
-                None => return None,
-                Some(m) => m.apply(),
+    fn random_outcome<R: Rng>(mut self, rng: &mut R) -> Outcome<Self::Agent> {
+        let mut buf = Vec::new();
+        loop {
+            if let Some(w) = self.winner() {
+                return Outcome::Win(w);
             }
-            if self.has_winner() {
-                return self.winner();
+            match self.choose_move(rng, &mut buf) {
+                None => return Outcome::Draw,
+                Some(m) => self.apply(m),
             }
         }
-        None
     }
+}
 
-    fn monte_carlo<R: Rng>(self, rng: &mut R, trials: u32) -> u32 {
+/// Games whose moves can be taken back, so searches can make and unmake
+/// moves on one position instead of cloning it for every move.
+pub trait ReversibleGame: Game {
+    /// Takes back `m`, which must be the last move applied.
+    fn undo(&mut self, m: Self::Move);
+
+    /// Plays random moves to the end of the game, then takes them all back.
+    fn playout<R: Rng>(&mut self, rng: &mut R) -> Outcome<Self::Agent>
+    where
+        Self: RandGame,
+    {
+        let mut buf = Vec::new();
+        let mut played = Vec::new();
+        let outcome = loop {
+            if let Some(w) = self.winner() {
+                break Outcome::Win(w);
+            }
+            match self.choose_move(rng, &mut buf) {
+                None => break Outcome::Draw,
+                Some(m) => {
+                    self.apply(m);
+                    played.push(m);
+                }
+            }
+        };
 
-        let ref_player = self.ref_player();
-        (0..trials)
-            .flat_map(|_| (&self).clone().random_outcome(rng))
-            .filter(move |c| *c == ref_player)
-            .map(|_| 1)
-            .sum()
+        while let Some(m) = played.pop() {
+            self.undo(m);
+        }
+        outcome
     }
 }
 
@@ -90,13 +207,34 @@ pub trait Game: Clone + Send {
 
     fn ref_player(&self) -> Self::Agent;
     fn new(&Self::Agent) -> Self;
-    fn possible_moves(&self) -> Vec<ValidMove<Self>>;
+
+    /// Fills `moves` with the legal moves from this position, replacing
+    /// whatever it held.  Callers can reuse one buffer across positions.
+    fn legal_moves(&self, moves: &mut Vec<Self::Move>);
+
+    /// Legal moves, each paired with its own copy of the position.
+    fn possible_moves(&self) -> Vec<ValidMove<Self>> {
+        let mut moves = Vec::new();
+        self.legal_moves(&mut moves);
+        moves
+            .into_iter()
+            .map(|m| {
+                ValidMove {
+                    valid_move: m,
+                    valid_for: self.clone(),
+                }
+            })
+            .collect()
+    }
 
     fn move_valid(&self, &Self::Move) -> bool;
     fn has_won(&self, agent: &Self::Agent) -> bool;
     fn apply(&mut self, Self::Move);
 
-    fn reachable(&self, _: &Self) -> bool {
+    /// Whether `g` can come up from this position after zero or more moves.
+    /// Searches use it to throw away what they know about positions the game
+    /// has moved past.  Assuming everything is reachable keeps everything.
+    fn reachable(&self, _g: &Self) -> bool {
         true
     }
 
@@ -133,6 +271,27 @@ pub trait Game: Clone + Send {
         self.winner().is_some()
     }
 
+    /// Whether the game is won, drawn or still going.  A game with no winner
+    /// and no moves left is a draw.
+    fn outcome(&self) -> Outcome<Self::Agent> {
+        match self.winner() {
+            Some(w) => Outcome::Win(w),
+            None => {
+                let mut moves = Vec::new();
+                self.legal_moves(&mut moves);
+                if moves.is_empty() {
+                    Outcome::Draw
+                } else {
+                    Outcome::Ongoing
+                }
+            }
+        }
+    }
+
+    fn is_over(&self) -> bool {
+        self.outcome() != Outcome::Ongoing
+    }
+
 
     fn try_move(&mut self, m: Self::Move) -> bool {
         let x = self.clone().verify_move(m);
@@ -67,6 +67,10 @@ impl Board {
     pub fn set(&mut self, i: usize, j: usize, x: Marker) {
         (*self).board[i * SIZE + j] = Square(Some(x));
     }
+
+    pub fn clear(&mut self, i: usize, j: usize) {
+        (*self).board[i * SIZE + j] = Square(None);
+    }
 }
 
 impl Default for Board {
@@ -104,6 +108,12 @@ pub struct TicTacToe {
     winner: Option<Marker>,
 }
 
+impl TicTacToe {
+    pub fn get(&self, i: usize, j: usize) -> Option<Marker> {
+        self.state.get(i, j).0
+    }
+}
+
 impl fmt::Display for TicTacToe {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "Acting: {}, Ref: {}", self.to_act, self.ref_player)?;
@@ -111,10 +121,25 @@ impl fmt::Display for TicTacToe {
     }
 }
 
+impl ReversibleGame for TicTacToe {
+    fn undo(&mut self, (i, j, marker): Self::Move) {
+        self.state.clear(i, j);
+        self.winner = None;
+        self.to_act = marker;
+    }
+}
+
 impl Game for TicTacToe {
     type Move = (usize, usize, Self::Agent);
     type Agent = Marker;
 
+    fn reachable(&self, g: &Self) -> bool {
+        if self.winner.is_some() {
+            return self == g;
+        }
+        self.state.board.iter().zip(g.state.board.iter()).all(|(a, b)| a.0.is_none() || a == b)
+    }
+
     fn agent_id(&self, &a: &Self::Agent) -> u32 {
         match a {
             Marker::X => 0,
@@ -126,6 +151,17 @@ impl Game for TicTacToe {
         self.winner
     }
 
+    fn outcome(&self) -> Outcome<Self::Agent> {
+        match self.winner {
+            Some(w) => Outcome::Win(w),
+            None => if self.state.board.iter().all(|s| s.0.is_some()) {
+                Outcome::Draw
+            } else {
+                Outcome::Ongoing
+            },
+        }
+    }
+
     fn ref_player(&self) -> Self::Agent {
         self.ref_player
     }
@@ -157,20 +193,20 @@ impl Game for TicTacToe {
         -1
     }
 
-    fn possible_moves(&self) -> Vec<ValidMove<Self>> {
+    fn legal_moves(&self, moves: &mut Vec<Self::Move>) {
         let marker = self.to_act();
-        let mut moves = Vec::new();
+        moves.clear();
+        if self.has_winner() {
+            return;
+        }
+
         for i in 0..SIZE {
             for j in 0..SIZE {
                 if !self.state.get(i, j).0.is_some() {
-                    moves.push(ValidMove {
-                        valid_move: (i, j, marker),
-                        valid_for: self.clone(),
-                    });
+                    moves.push((i, j, marker));
                 }
             }
         }
-        moves
     }
 
 
@@ -213,7 +249,7 @@ impl Game for TicTacToe {
                 if (0..REQ).map(|n| value(i + n, j + n, marker)).sum::<usize>() == REQ {
                     return true;
                 }
-                if (0..REQ).map(|n| value(i + n, j - n, marker)).sum::<usize>() == REQ {
+                if (0..REQ).map(|n| value(i + n, j.wrapping_sub(n), marker)).sum::<usize>() == REQ {
                     return true;
                 }
             }
@@ -240,17 +276,59 @@ impl ParseGame for TicTacToe {
     }
 }
 
-impl RandGame for TicTacToe {
-    fn random_move<R: rand::Rng>(&mut self, rng: &mut R) -> Option<ValidMoveMut<Self>> {
-        let mut moves = self.possible_moves();
-        if moves.len() == 0 {
-            None
-        } else {
-            rng.shuffle(&mut moves);
-            Some(ValidMoveMut {
-                valid_move: moves[0].valid_move,
-                valid_for: self,
-            })
-        }
+impl RandGame for TicTacToe {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use self::Marker::*;
+
+    #[test]
+    fn test_outcome() {
+        let mut game = TicTacToe::new(&X);
+        assert_eq!(game.outcome(), Outcome::Ongoing);
+
+        // X O X
+        // X O O
+        // O X X
+        game.try_moves(
+            vec![(0, 0, X), (0, 1, O), (0, 2, X), (1, 1, O), (1, 0, X), (1, 2, O), (2, 1, X),
+                 (2, 0, O), (2, 2, X)]
+                .into_iter(),
+        );
+        assert_eq!(game.winner(), None);
+        assert_eq!(game.outcome(), Outcome::Draw);
+        assert!(game.possible_moves().is_empty());
+    }
+
+    #[test]
+    fn test_wins_on_either_diagonal() {
+        // X O O      O O X
+        // . X .      . X .
+        // . . X      X . .
+        let mut game = TicTacToe::new(&X);
+        game.try_moves(vec![(0, 0, X), (0, 1, O), (1, 1, X), (0, 2, O), (2, 2, X)].into_iter());
+        assert_eq!(game.winner(), Some(X));
+
+        // Walking down-left from the left edge runs off the board, rather
+        // than wrapping around to the other side.
+        let mut game = TicTacToe::new(&X);
+        game.try_moves(vec![(0, 2, X), (0, 0, O), (1, 1, X), (0, 1, O), (2, 0, X)].into_iter());
+        assert_eq!(game.winner(), Some(X));
+    }
+
+    #[test]
+    fn test_legal_moves_reuses_buffer() {
+        let mut game = TicTacToe::new(&X);
+        let mut moves = Vec::new();
+        game.legal_moves(&mut moves);
+        assert_eq!(moves.len(), SIZE * SIZE);
+
+        game.try_move((1, 1, X));
+        game.legal_moves(&mut moves);
+        assert_eq!(moves.len(), SIZE * SIZE - 1);
+        assert!(moves.iter().all(|&(i, j, m)| m == O && (i, j) != (1, 1)));
+        let possible: Vec<_> = game.possible_moves().iter().map(|m| *m.valid_move()).collect();
+        assert_eq!(possible, moves);
     }
 }
@@ -96,12 +96,19 @@ impl Game for TrivialGame {
     }
 
 
+    fn reachable(&self, g: &Self) -> bool {
+        self.states.iter().zip(g.states.iter()).all(|(a, b)| a <= b)
+    }
+
     fn move_valid(&self, _: &Self::Move) -> bool {
         true
     }
 
-    fn possible_moves(&self) -> Vec<ValidMove<Self>> {
-        self.verify_move(()).into_iter().collect()
+    fn legal_moves(&self, moves: &mut Vec<Self::Move>) {
+        moves.clear();
+        if !self.has_winner() {
+            moves.push(());
+        }
     }
 
     fn apply(&mut self, _: Self::Move) {
@@ -110,6 +117,13 @@ impl Game for TrivialGame {
     }
 }
 
+impl ReversibleGame for TrivialGame {
+    fn undo(&mut self, _: Self::Move) {
+        self.to_act = self.to_act.other();
+        self.states[self.agent_id(&self.to_act) as usize].0 -= 1;
+    }
+}
+
 impl RandGame for TrivialGame {}
 
 impl ParseGame for TrivialGame {
@@ -49,7 +49,9 @@ mod tests {
     //     b.iter(|| {
     //         let mut strategy = Negamax::create(NegamaxParams {
     //             max_depth: 3,
-    //             trials: 5,
+    //             timeout: 1000,
+    //             table_size: 1 << 20,
+    //             evaluator: MonteCarlo::new(5),
     //         });
     //         strategy.decide(&ConnectFour::new(&Color::R));
     //     });
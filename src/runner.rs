@@ -1,8 +1,8 @@
-use game::{RandGame, Game, ParseGame};
+use game::{self, RandGame, Game, ParseGame, Outcome};
 use std::fmt;
 use std::sync::mpsc;
 use std::io;
-use rand;
+use rand::{self, Rng};
 use strategies::Strategy;
 pub trait Player<G>
 where
@@ -11,6 +11,9 @@ where
     G::Move: Send + Ord,
 {
     fn choose_move(&mut self, game: &G, output: OnceSender<G::Move>);
+    /// Called with every position where the other player is to act, so the
+    /// player can think on their time.  It mustn't block.
+    fn ponder(&mut self, _game: &G) {}
     fn display_name(&self) -> &str;
     fn player_type(&self) -> &str;
     fn full_name(&self) -> String {
@@ -195,6 +198,10 @@ where
         println!("CHOSE MOVE: {:?}", m);
         output.send(m).expect("Send failed.");
     }
+
+    fn ponder(&mut self, board: &G) {
+        self.strategy.ponder(board);
+    }
 }
 
 impl<S, G: Game + fmt::Display> AIPlayer<G, S>
@@ -216,6 +223,9 @@ pub struct Runner<'a, G: Game + 'a> {
     board: G,
     players: (Plr<'a, G>, Plr<'a, G>),
     channel: (mpsc::Sender<G::Move>, mpsc::Receiver<G::Move>),
+    // Whether players think while waiting for their turn.  Off unless asked
+    // for, since it keeps threads running between moves.
+    pondering: bool,
 }
 
 impl<'a, G> Runner<'a, G>
@@ -225,18 +235,34 @@ where
     G::Move: Send + Ord,
 {
     pub fn new(p1: Plr<'a, G>, p2: Plr<'a, G>) -> Self {
-        // Self::new_with_first_to_act(rand::random::<G::Agent>(), p1, p2)
-        Self::new_with_first_to_act(rand::random::<G::Agent>(), p1, p2)
+        Self::new_with_seed(None, p1, p2)
+    }
+
+    /// Like `new`, but with the coin flip for who starts drawn from `seed`, so
+    /// that together with seeded players a whole game can be replayed.
+    pub fn new_with_seed(seed: Option<[u32; 4]>, p1: Plr<'a, G>, p2: Plr<'a, G>) -> Self {
+        let agent = game::seeded_rng(seed, 0).gen::<G::Agent>();
+        Self::new_with_first_to_act(agent, p1, p2)
     }
 
     pub fn new_with_first_to_act(agent: G::Agent, p1: Plr<'a, G>, p2: Plr<'a, G>) -> Self {
+        Self::new_with_board(G::new(&agent), p1, p2)
+    }
+
+    pub fn new_with_board(board: G, p1: Plr<'a, G>, p2: Plr<'a, G>) -> Self {
         Runner {
-            board: G::new(&agent),
+            board: board,
             players: (p1, p2),
             channel: mpsc::channel(),
+            pondering: false,
         }
     }
 
+    /// Lets players think about the next move while the other one decides.
+    pub fn set_pondering(&mut self, pondering: bool) {
+        self.pondering = pondering;
+    }
+
     fn init(&mut self) {
         println!("Player 1 is {}", self.players.0.full_name());
         println!("Player 2 is {}", self.players.1.full_name());
@@ -245,14 +271,23 @@ where
         println!("{} goes first!", self.board.to_act());
     }
 
-    fn check_winner(&mut self) -> bool {
-        self.board.has_winner()
+    fn check_over(&mut self) -> bool {
+        self.board.is_over()
     }
 
     fn step(&mut self) {
         println!("{}", self.board);
         // Hacky: Generalize to multi player games.
         let to_act_id = self.board.agent_id(&self.board.to_act());
+        if self.pondering {
+            // The player waiting for the move gets to think about it.
+            if to_act_id == 0 {
+                (*self).players.1.ponder(&self.board);
+            } else {
+                (*self).players.0.ponder(&self.board);
+            }
+        }
+
         if to_act_id == 0 {
             (*self).players.0.choose_move(
                 &self.board,
@@ -277,19 +312,52 @@ where
     }
 
 
-    fn game_loop(&mut self) {
-        while !self.check_winner() {
+    fn game_loop(&mut self) -> Outcome<G::Agent> {
+        while !self.check_over() {
             self.step()
         }
 
-        let winner = self.board.winner().unwrap();
-        println!("Winner is: {}", winner);
+        // Pondering over the final position just stops any still going.
+        if self.pondering {
+            self.players.0.ponder(&self.board);
+            self.players.1.ponder(&self.board);
+        }
+
+        println!("{}", self.board);
+        let outcome = self.board.outcome();
+        match outcome {
+            Outcome::Win(winner) => println!("Winner is: {}", winner),
+            Outcome::Draw => println!("It's a draw."),
+            Outcome::Ongoing => unreachable!(),
+        }
+        outcome
     }
 
-    pub fn run<'b>(p1: Plr<'b, G>, p2: Plr<'b, G>) -> Option<G::Agent> {
+    /// Plays the game through to the end.
+    pub fn play(&mut self) -> Outcome<G::Agent> {
+        self.init();
+        self.game_loop()
+    }
+
+    pub fn run<'b>(p1: Plr<'b, G>, p2: Plr<'b, G>) -> Outcome<G::Agent> {
         let mut runner = Runner::new(p1, p2);
         runner.init();
-        runner.game_loop();
-        runner.board.winner()
+        runner.game_loop()
+    }
+
+    pub fn run_with_seed<'b>(
+        seed: Option<[u32; 4]>,
+        p1: Plr<'b, G>,
+        p2: Plr<'b, G>,
+    ) -> Outcome<G::Agent> {
+        let mut runner = Runner::new_with_seed(seed, p1, p2);
+        runner.init();
+        runner.game_loop()
+    }
+
+    pub fn run_with_board<'b>(board: G, p1: Plr<'b, G>, p2: Plr<'b, G>) -> Outcome<G::Agent> {
+        let mut runner = Runner::new_with_board(board, p1, p2);
+        runner.init();
+        runner.game_loop()
     }
 }
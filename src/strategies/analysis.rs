@@ -0,0 +1,58 @@
+use game::{Game, Score, Value};
+use std::fmt;
+use std::time::Duration;
+
+/// How a search rated a move, from the point of view of the player making
+/// it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rating {
+    /// Share of the simulations through the move that were won, counting
+    /// draws as half.
+    WinRate(f64),
+    /// The evaluation after searching the move.
+    Score(Score),
+    /// The result with best play, once the search has proven it.
+    Proven(Value),
+}
+
+/// A move from the position searched, and what the search made of it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RatedMove<M> {
+    pub m: M,
+    /// Simulations through the move, for searches that sample.
+    pub visits: usize,
+    /// `None` if the search never got to the move.
+    pub rating: Option<Rating>,
+}
+
+/// What a search found in a position.
+pub struct Analysis<G: Game> {
+    /// Every legal move, best first.
+    pub moves: Vec<RatedMove<G::Move>>,
+    /// The line of play the search expects, starting with the best move.
+    pub pv: Vec<G::Move>,
+    /// Simulations run, or positions searched, to get here.
+    pub nodes: usize,
+    pub elapsed: Duration,
+}
+
+impl<G: Game> Analysis<G> {
+    /// The move the search would play, unless there are no moves.
+    pub fn best(&self) -> Option<G::Move> {
+        self.moves.first().map(|r| r.m)
+    }
+}
+
+impl<G: Game> fmt::Debug for Analysis<G>
+where
+    G::Move: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Analysis")
+            .field("moves", &self.moves)
+            .field("pv", &self.pv)
+            .field("nodes", &self.nodes)
+            .field("elapsed", &self.elapsed)
+            .finish()
+    }
+}
@@ -0,0 +1,210 @@
+use game::{self, Game, Outcome, RandGame, ReversibleGame, Score};
+use game::connectfour::ConnectFour;
+use game::tictactoe::{self, TicTacToe};
+use rand::XorShiftRng;
+
+/// Score of a won position.  Evaluators must stay well inside this so that
+/// real wins and losses always dominate.
+pub const WIN: Score = 1_000_000;
+
+/// Static evaluation used to score negamax leaves.  Scores are from the point
+/// of view of the player to act.
+pub trait Evaluator<G: Game> {
+    fn evaluate(&mut self, game: &G) -> Score;
+
+    /// Seeds any random numbers the evaluator uses, so it scores the same
+    /// way every time.  Does nothing by default.
+    fn reseed(&mut self, _seed: [u32; 4]) {}
+}
+
+/// Scores a position by the result of random playouts: one for each win,
+/// less one for each loss, with draws counting for neither.
+pub struct MonteCarlo {
+    trials: u32,
+    rng: XorShiftRng,
+}
+
+impl MonteCarlo {
+    pub fn new(trials: u32) -> Self {
+        MonteCarlo {
+            trials: trials,
+            rng: game::seeded_rng(None, 0),
+        }
+    }
+}
+
+impl<G: RandGame + ReversibleGame> Evaluator<G> for MonteCarlo {
+    fn evaluate(&mut self, game: &G) -> Score {
+        // Play out every trial on one copy, taking the moves back after each.
+        let mut scratch = game.clone();
+        let reference = game.ref_player();
+        let mut score = 0;
+        for _ in 0..self.trials {
+            score += match scratch.playout(&mut self.rng) {
+                Outcome::Win(p) if p == reference => 1,
+                Outcome::Draw => 0,
+                _ => -1,
+            };
+        }
+        let weight = game.player_weight(&game.to_act());
+        score * weight
+    }
+
+    fn reseed(&mut self, seed: [u32; 4]) {
+        self.rng = game::seeded_rng(Some(seed), 0);
+    }
+}
+
+/// Counts windows of `needed` cells holding only one player's pieces and
+/// one or two empty cells.
+pub struct ConnectFourEvaluator {
+    pub three: Score,
+    pub two: Score,
+}
+
+impl Default for ConnectFourEvaluator {
+    fn default() -> Self {
+        ConnectFourEvaluator { three: 5, two: 2 }
+    }
+}
+
+impl ConnectFourEvaluator {
+    fn window_score(&self, pieces: usize, needed: usize) -> Score {
+        if pieces + 1 == needed {
+            self.three
+        } else if pieces + 2 == needed {
+            self.two
+        } else {
+            0
+        }
+    }
+}
+
+impl Evaluator<ConnectFour> for ConnectFourEvaluator {
+    fn evaluate(&mut self, game: &ConnectFour) -> Score {
+        let width = game.width() as isize;
+        let height = game.height() as isize;
+        let needed = game.needed() as isize;
+        let me = game.to_act();
+
+        let mut score = 0;
+        for &(dr, dc) in &[(0, 1), (1, 0), (1, 1), (1, -1)] {
+            for r in 0..height {
+                for c in 0..width {
+                    let (er, ec) = (r + dr * (needed - 1), c + dc * (needed - 1));
+                    if er < 0 || er >= height || ec < 0 || ec >= width {
+                        continue;
+                    }
+
+                    let (mut mine, mut theirs) = (0, 0);
+                    for k in 0..needed {
+                        match game.get((r + dr * k) as usize, (c + dc * k) as usize) {
+                            Some(color) => if color == me { mine += 1 } else { theirs += 1 },
+                            None => (),
+                        }
+                    }
+
+                    if theirs == 0 {
+                        score += self.window_score(mine, needed as usize);
+                    } else if mine == 0 {
+                        score -= self.window_score(theirs, needed as usize);
+                    }
+                }
+            }
+        }
+        score
+    }
+}
+
+/// Counts lines still open to each player, weighting each by how many of
+/// the player's markers it already holds.
+pub struct TicTacToeEvaluator;
+
+impl Evaluator<TicTacToe> for TicTacToeEvaluator {
+    fn evaluate(&mut self, game: &TicTacToe) -> Score {
+        let size = tictactoe::SIZE as isize;
+        let req = tictactoe::REQ as isize;
+        let me = game.to_act();
+
+        let mut score = 0;
+        for &(di, dj) in &[(0, 1), (1, 0), (1, 1), (1, -1)] {
+            for i in 0..size {
+                for j in 0..size {
+                    let (ei, ej) = (i + di * (req - 1), j + dj * (req - 1));
+                    if ei < 0 || ei >= size || ej < 0 || ej >= size {
+                        continue;
+                    }
+
+                    let (mut mine, mut theirs) = (0, 0);
+                    for k in 0..req {
+                        match game.get((i + di * k) as usize, (j + dj * k) as usize) {
+                            Some(marker) => if marker == me { mine += 1 } else { theirs += 1 },
+                            None => (),
+                        }
+                    }
+
+                    if mine > 0 && theirs == 0 {
+                        score += (10 as Score).pow(mine - 1);
+                    } else if theirs > 0 && mine == 0 {
+                        score -= (10 as Score).pow(theirs - 1);
+                    }
+                }
+            }
+        }
+        score
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use game::connectfour::Color::*;
+    use game::tictactoe::Marker;
+
+    #[test]
+    fn test_connect_four_favors_open_three() {
+        let mut game = ConnectFour::new(&R);
+        game.try_moves(vec![(2, R), (2, B), (3, R), (3, B), (4, R)].into_iter());
+
+        let mut eval = ConnectFourEvaluator::default();
+        let for_b = eval.evaluate(&game);
+        game.try_move((0, B));
+        let for_r = eval.evaluate(&game);
+        assert!(for_b < 0);
+        assert!(for_r > 0);
+    }
+
+    #[test]
+    fn test_monte_carlo_reseeds() {
+        let game = ConnectFour::new(&R);
+        let (mut a, mut b) = (MonteCarlo::new(50), MonteCarlo::new(50));
+        Evaluator::<ConnectFour>::reseed(&mut a, [1, 2, 3, 4]);
+        Evaluator::<ConnectFour>::reseed(&mut b, [1, 2, 3, 4]);
+        let scores: Vec<Score> = (0..5).map(|_| a.evaluate(&game)).collect();
+        assert_eq!(scores, (0..5).map(|_| b.evaluate(&game)).collect::<Vec<Score>>());
+    }
+
+    #[test]
+    fn test_monte_carlo_scores_draws_as_neutral() {
+        // X O X
+        // X O O
+        // O X .
+        let mut game = TicTacToe::new(&Marker::X);
+        game.try_moves(
+            vec![(0, 0, Marker::X), (0, 1, Marker::O), (0, 2, Marker::X), (1, 1, Marker::O),
+                 (1, 0, Marker::X), (1, 2, Marker::O), (2, 1, Marker::X), (2, 0, Marker::O)]
+                .into_iter(),
+        );
+        assert_eq!(MonteCarlo::new(10).evaluate(&game), 0);
+    }
+
+    #[test]
+    fn test_tictactoe_counts_open_lines() {
+        let mut game = TicTacToe::new(&Marker::X);
+        assert_eq!(TicTacToeEvaluator.evaluate(&game), 0);
+
+        game.try_move((1, 1, Marker::X));
+        // The center sits on four lines, all still open to X.
+        assert_eq!(TicTacToeEvaluator.evaluate(&game), -4);
+    }
+}
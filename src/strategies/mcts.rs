@@ -1,189 +1,221 @@
-use super::*;
+use super::mcts_core::{self, Backend, Budget, Entry, MemoryLimit, Scratch, Size, StatsTable};
+use super::rollout::{RolloutPolicy, Uniform};
 use game::*;
-use rand::{Rng, XorShiftRng};
-use rand;
-use std::fmt;
-use std::sync::*;
-use std::thread;
-use std::time::Duration;
-
-#[derive(Debug, Clone)]
-struct Stats {
-    wins: usize,
-    losses: usize,
-    visits: usize,
-}
-
+use rand::XorShiftRng;
 use std::collections::HashMap;
 use std::hash::Hash;
-#[derive(Clone)]
-pub struct MCTSParams {
-    // Time limit in ms.
-    pub timeout: u64,
-    pub c: f64,
-}
+use std::sync::*;
 
-struct State<G: Hash + PartialEq + Eq> {
-    stats: HashMap<G, Stats>,
-    cur: Option<G>,
+pub use super::mcts_core::MCTSParams;
+
+/// MCTS run on the calling thread, playing out new positions with `P`.
+pub type MCTS<G, P = Uniform> = mcts_core::MCTS<G, Sequential<G, P>>;
+
+struct State<G: Game, P> {
+    stats: HashMap<G, Entry>,
+    rollout: P,
     rng: XorShiftRng,
+    scratch: Scratch<G>,
+    updates: Vec<(G, Entry)>,
 }
 
-pub struct MCTS<G: Hash + Eq + RandGame + 'static> {
+/// Runs simulations one after another and applies each result straight away.
+pub struct Sequential<G: Game, P = Uniform> {
     params: MCTSParams,
-    state: Arc<Mutex<State<G>>>,
+    state: Mutex<State<G, P>>,
 }
 
-impl<G: RandGame + Eq + Hash + 'static> MCTS<G> {
-    fn simulate(&self, game: &G) -> Option<G::Agent> {
-        let acting = game.to_act();
-        let nexts = game.possible_moves();
-        let winner = if nexts.is_empty() {
-            game.winner()
-        } else {
-            let parent_visits = {
-                let stats_cache = &self.state.lock().unwrap().stats;
-                stats_cache.get(game).map(|s| s.visits).unwrap_or(1)
-            };
-            let g = self.select(nexts, parent_visits, acting);
-            self.simulate(&g)
-        };
+impl<G: Game + Hash + Eq, P> StatsTable<G> for Sequential<G, P> {
+    fn lookup(&self, g: &G) -> Option<Entry> {
+        self.state.lock().unwrap().stats.lookup(g)
+    }
+}
 
-        let stats_cache = &mut self.state.lock().unwrap().stats;
-        let stats = stats_cache.entry(game.clone()).or_insert(Stats {
-            wins: 0,
-            losses: 0,
-            visits: 0,
-        });
+impl<G: RandGame + Hash + Eq, P: RolloutPolicy<G>> Backend<G> for Sequential<G, P> {
+    type Rollout = P;
 
-        stats.visits += 1;
-        if let Some(w) = winner {
-            if w == acting {
-                stats.wins += 1;
-            } else {
-                stats.losses += 1;
-            }
+    fn create(params: MCTSParams, rollout: P) -> Self {
+        let rng = seeded_rng(params.seed, 0);
+        Sequential {
+            params: params,
+            state: Mutex::new(State {
+                stats: HashMap::new(),
+                rollout: rollout,
+                rng: rng,
+                scratch: Scratch::default(),
+                updates: Vec::new(),
+            }),
         }
-
-        winner
     }
 
-    fn key(&self, (g, s): (&G, &Stats), n: f64, acting: G::Agent) -> f64 {
-        let wins = if acting == g.to_act() {
-            s.wins
-        } else {
-            s.losses
-        } as f64;
-        let visits = s.visits as f64;
-        wins / visits + self.params.c * (n.ln() / visits).sqrt()
+    fn search(&self, root: &G, mut budget: Budget) {
+        while let Some(n) = budget.next_batch() {
+            let mut state = self.state.lock().unwrap();
+            let state = &mut *state;
+            for _ in 0..n {
+                mcts_core::simulate(
+                    &self.params,
+                    &state.stats,
+                    &mut state.rollout,
+                    &mut state.rng,
+                    root,
+                    &mut state.scratch,
+                    &mut state.updates,
+                );
+                for (g, s) in state.updates.drain(..) {
+                    *state.stats.entry(g).or_insert(Entry::ZERO) += s;
+                }
+            }
+            let size = Size::of_table::<G>(state.stats.len());
+            if let Some(target) = MemoryLimit::target(self.params.memory_limit, size) {
+                mcts_core::evict(&mut state.stats, root, target);
+            }
+        }
     }
 
-    fn select(&self, choices: Vec<ValidMove<G>>, parent_visits: usize, acting: G::Agent) -> G {
-        let n = choices.len();
-        let mut state = self.state.lock().unwrap();
-
-        if choices.is_empty() {
-            panic!("Shouldn't have gotten here.")
-        }
+    fn prune(&self, root: &G) {
+        self.state.lock().unwrap().stats.retain(|k, _| root.reachable(k));
+    }
 
-        let i = state.rng.gen::<usize>();
-        let random_choice = choices[i % n].clone().apply();
-        let games: Vec<_> = choices
-            .into_iter()
-            .flat_map(|m| {
-                let game = m.apply();
-                let stats = state.stats.get(&game);
-                stats.map(|s| (game, s))
-            })
-            .collect();
-
-        if games.len() == n {
-            let x = games.into_iter().max_by(|t1, t2| {
-                let t1 = (&t1.0, t1.1);
-                let t2 = (&t2.0, t2.1);
-                self.key(t1, parent_visits as f64, acting)
-                    .partial_cmp(&self.key(t2, parent_visits as f64, acting))
-                    .unwrap()
-            });
-            x.unwrap().0
-        } else {
-            random_choice
-        }
+    fn size(&self) -> Size {
+        Size::of_table::<G>(self.state.lock().unwrap().stats.len())
     }
 }
 
-impl<G> Strategy<G> for MCTS<G>
-where
-    G: RandGame + fmt::Display + Hash + Eq,
-{
-    type Params = MCTSParams;
-
-    fn decide(&mut self, game: &G) -> G::Move {
-        {
-            (*self.state.lock().unwrap()).cur = Some(game.clone());
-        }
-        thread::sleep(Duration::from_millis(self.params.timeout));
-        let state = &self.state.lock().unwrap();
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::{Rating, Strategy};
+    use game::connectfour::{Color, ConnectFour};
+    use game::dots::{Dots, DotsPlayer};
+    use strategies::mcts_core::test::{play_moves, sim_params};
+    use strategies::rollout::Tactical;
+    use std::thread;
+
+    #[test]
+    fn test_keeps_reachable_stats() {
+        let mut game = ConnectFour::new(&Color::R);
+        let mut strategy = MCTS::<ConnectFour>::create(sim_params(500));
+        let m = strategy.decide(&game);
+        assert!(game.move_valid(&m));
+        assert_eq!(strategy.backend().stats(&game).map(|s| s.visits), Some(500));
+
+        game.try_move(m);
+        let reply = (3, game.to_act());
+        let mut after = game.clone();
+        after.try_move(reply);
+        let kept = strategy.backend().stats(&after).map(|s| s.visits).unwrap_or(0);
+        strategy.decide(&after);
+        // Positions we've moved past are gone, and the new root builds on
+        // what it had.
+        assert_eq!(strategy.backend().stats(&game), None);
+        assert_eq!(strategy.backend().stats(&after).map(|s| s.visits), Some(kept + 500));
+    }
 
-        let nexts = game.possible_moves().into_iter().map(|m| {
-            let vm = *m.valid_move();
-            (vm, m.apply())
+    #[test]
+    fn test_stays_within_memory_limit() {
+        let game = ConnectFour::new(&Color::R);
+        let mut strategy = MCTS::<ConnectFour>::create(MCTSParams {
+            batch_size: 20,
+            memory_limit: Some(MemoryLimit::Nodes(200)),
+            ..sim_params(2000)
         });
+        strategy.decide(&game);
+        let size = strategy.backend().size();
+        assert!(size.nodes <= 200, "{:?}", size);
+        assert_eq!(size, Size::of_table::<ConnectFour>(size.nodes));
+        assert_eq!(strategy.backend().stats(&game).map(|s| s.visits), Some(2000));
+    }
 
-        // let nexts2 = game.possible_moves().into_iter().map(|m| {
-        //     let vm = *m.valid_move();
-        //     (vm, m.apply())
-        // });
-
-        // for (_, g) in nexts2 {
-        //     let s = state.stats.get(&g).unwrap();
-        //     println!("{:?}\n{}", s, g);
-        // }
-
-        let (best, stats) = nexts
-            .into_iter()
-            .flat_map(|(m, g)| state.stats.get(&g).map(|s| (m, s)))
-            .max_by(|t1, t2| {
-                let s1 = t1.1;
-                let s2 = t2.1;
-                s1.visits.cmp(&s2.visits)
-                // (s1.losses as f64 / s1.visits as f64)
-                //     .partial_cmp(&(s2.losses as f64 / s2.visits as f64))
-                //     .unwrap_or(Less)
-            })
-            .unwrap();
-
-        println!("Best: {:?}", stats);
-        best
+    #[test]
+    fn test_analysis_ranks_moves() {
+        let game = ConnectFour::new(&Color::R);
+        let mut strategy = MCTS::<ConnectFour>::create(sim_params(500));
+        let analysis = strategy.analyze(&game);
+        assert_eq!(analysis.nodes, 500);
+        assert_eq!(analysis.moves.len(), 7);
+        // Every simulation after the first went through one of the moves.
+        assert_eq!(analysis.moves.iter().map(|r| r.visits).sum::<usize>(), 499);
+        assert!(analysis.moves.windows(2).all(|w| w[0].visits >= w[1].visits));
+        for r in &analysis.moves {
+            match r.rating {
+                Some(Rating::WinRate(rate)) => assert!(rate >= 0.0 && rate <= 1.0),
+                rating => panic!("Unexpected rating {:?}", rating),
+            }
+        }
+        assert_eq!(analysis.pv.first(), analysis.best().as_ref());
+        assert!(analysis.pv.len() > 1);
     }
 
-    fn create(params: MCTSParams) -> Self {
-        let seed = rand::random::<[u32; 4]>();
-        let rng: XorShiftRng = rand::SeedableRng::from_seed(seed);
-        let state = Arc::new(Mutex::new(State {
-            cur: None,
-            stats: HashMap::new(),
-            rng: rng,
-        }));
-        let new = Self {
-            params: params.clone(),
-            state: state.clone(),
+    #[test]
+    fn test_seed_reproduces_search() {
+        let game = ConnectFour::new(&Color::R);
+        let analyze = |seed| {
+            let mut strategy = MCTS::<ConnectFour>::create(MCTSParams {
+                seed: Some(seed),
+                ..sim_params(300)
+            });
+            strategy.analyze(&game).moves
         };
-        let it = Self {
-            params: params,
-            state: state,
+        assert_eq!(analyze([1, 2, 3, 4]), analyze([1, 2, 3, 4]));
+        assert!(analyze([1, 2, 3, 4]) != analyze([5, 6, 7, 8]));
+    }
+
+    #[test]
+    fn test_seed_reproduces_dots_search() {
+        // A fresh game each time, as a new process would have, with nothing
+        // shared between the two searches but the seed.
+        let analyze = || {
+            let game = Dots::new(&DotsPlayer::A);
+            let mut strategy = MCTS::<Dots>::create(MCTSParams {
+                seed: Some([1, 2, 3, 4]),
+                ..sim_params(300)
+            });
+            strategy.analyze(&game).moves
         };
-        thread::spawn(move || loop {
-            let game = {
-                match it.state.lock().unwrap().cur.clone() {
-                    None => continue,
-                    Some(g) => g,
-                }
-            };
+        assert_eq!(analyze(), analyze());
+    }
 
-            it.simulate(&game);
-        });
-        new
+    #[test]
+    fn test_with_rollout() {
+        let game = play_moves(&ConnectFour::new(&Color::R), &[6, 0, 6, 1, 5, 2]);
+        let mut strategy: MCTS<ConnectFour, Tactical<ConnectFour>> = MCTS::with_rollout(
+            sim_params(200),
+            Tactical::default(),
+        );
+        // Anything but blocking column 3 loses at once.
+        assert_eq!(strategy.decide(&game), (3, Color::R));
+    }
 
+    #[test]
+    fn test_ponders_on_opponents_time() {
+        let mut game = ConnectFour::new(&Color::R);
+        let params = sim_params(100);
+        let mut strategy = MCTS::<ConnectFour>::create(params);
+        strategy.ponder(&game);
+        assert!(!strategy.is_pondering());
+
+        let mut strategy = MCTS::<ConnectFour>::create(MCTSParams { ponder: true, ..params });
+        let m = strategy.decide(&game);
+        game.try_move(m);
+        let mut after_reply = game.clone();
+        after_reply.try_move((3, game.to_act()));
+        fn visits(strategy: &MCTS<ConnectFour>, g: &ConnectFour) -> usize {
+            strategy.backend().stats(g).map(|s| s.visits).unwrap_or(0)
+        }
+        let before = visits(&strategy, &after_reply);
+        strategy.ponder(&game);
+        assert!(strategy.is_pondering());
+        while visits(&strategy, &after_reply) == before {
+            thread::yield_now();
+        }
+        strategy.stop_pondering();
+        assert!(!strategy.is_pondering());
+        let pondered = visits(&strategy, &after_reply);
+
+        // Deciding builds on what the pondering found.
+        game = after_reply;
+        strategy.decide(&game);
+        assert_eq!(visits(&strategy, &game), pondered + 100);
     }
 }
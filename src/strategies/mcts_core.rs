@@ -0,0 +1,1171 @@
+use super::*;
+use super::mcts_selection::{Policy, Rave, SelectionPolicy};
+use super::rollout::RolloutPolicy;
+use game::*;
+use rand::Rng;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::iter;
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::ops::{Add, AddAssign};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Results of the simulations through a position, from the point of view of
+/// the player to act there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    pub wins: usize,
+    pub losses: usize,
+    pub draws: usize,
+    pub visits: usize,
+}
+
+impl Stats {
+    pub const ZERO: Stats = Stats {
+        wins: 0,
+        losses: 0,
+        draws: 0,
+        visits: 0,
+    };
+
+    /// A single simulation with result `value`.
+    pub fn single(value: Value) -> Self {
+        let mut stats = Stats::ZERO;
+        stats.visits = 1;
+        match value {
+            Value::Win => stats.wins = 1,
+            Value::Loss => stats.losses = 1,
+            Value::Draw => stats.draws = 1,
+        }
+        stats
+    }
+
+    /// Wins plus half the draws.
+    pub fn reward(&self) -> f64 {
+        self.wins as f64 + 0.5 * self.draws as f64
+    }
+
+    /// The same results from the other player's point of view.
+    pub fn flipped(&self) -> Self {
+        Stats {
+            wins: self.losses,
+            losses: self.wins,
+            ..*self
+        }
+    }
+}
+
+impl Add for Stats {
+    type Output = Stats;
+    fn add(self, rhs: Self) -> Self::Output {
+        Stats {
+            wins: self.wins + rhs.wins,
+            losses: self.losses + rhs.losses,
+            draws: self.draws + rhs.draws,
+            visits: self.visits + rhs.visits,
+        }
+    }
+}
+
+impl AddAssign for Stats {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl<'a> iter::Sum<&'a Stats> for Stats {
+    fn sum<I>(iter: I) -> Self
+    where
+        I: Iterator<Item = &'a Self>,
+    {
+        iter.fold(Stats::ZERO, |a, b| a + *b)
+    }
+}
+
+/// Everything a table keeps about a position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    /// Simulations through the position itself.
+    pub stats: Stats,
+    /// Simulations from the parent in which the move leading here was played
+    /// at any later point by the same player, for RAVE.
+    pub amaf: Stats,
+    /// The result with best play, once the search has proven it, for the
+    /// player to act.
+    pub proven: Option<Value>,
+}
+
+impl Entry {
+    pub const ZERO: Entry = Entry {
+        stats: Stats::ZERO,
+        amaf: Stats::ZERO,
+        proven: None,
+    };
+}
+
+impl Add for Entry {
+    type Output = Entry;
+    fn add(self, rhs: Self) -> Self::Output {
+        Entry {
+            stats: self.stats + rhs.stats,
+            amaf: self.amaf + rhs.amaf,
+            // A position can only be proven one way.
+            proven: self.proven.or(rhs.proven),
+        }
+    }
+}
+
+impl AddAssign for Entry {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl<'a> iter::Sum<&'a Entry> for Entry {
+    fn sum<I>(iter: I) -> Self
+    where
+        I: Iterator<Item = &'a Self>,
+    {
+        iter.fold(Entry::ZERO, |a, b| a + *b)
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct MCTSParams {
+    // Time limit in ms, if any.
+    pub timeout: Option<u64>,
+    // Simulation limit, if any.  The search stops at whichever limit it
+    // reaches first, and with neither it stops at the default timeout.
+    pub max_sims: Option<u64>,
+    // Simulations run between checks of the limits.
+    pub batch_size: u64,
+    // How the walk down the tree picks children.
+    pub policy: Policy,
+    // Whether to blend all-moves-as-first results into selection, and how.
+    pub rave: Option<Rave>,
+    // Threads searching at once, for the backends that use threads.
+    pub workers: u64,
+    pub merger_queue_bound: usize,
+    // Worker updates merged between sending the merged stats back out.
+    pub merger_batch_size: u64,
+    // Minimum time in ms between worker stats flushes.
+    pub min_flush_interval: u64,
+    // Losses each simulation under way counts as on its path, for the
+    // backends that run simulations at once against the same stats.
+    pub virtual_loss: usize,
+    // Whether to keep searching while the opponent thinks.
+    pub ponder: bool,
+    // Whether positions reached by different move orders share a node, for
+    // the backends that keep an explicit tree.
+    pub transpositions: bool,
+    // How much the stats may take up before the least useful are evicted,
+    // if there's a limit.
+    pub memory_limit: Option<MemoryLimit>,
+    // Seed for the search's random numbers, if any, to play the same way
+    // again with the same `workers`.  Searches that stop at the timeout, and
+    // pondering, still depend on timing.
+    pub seed: Option<[u32; 4]>,
+}
+
+impl Default for MCTSParams {
+    fn default() -> Self {
+        MCTSParams {
+            timeout: Some(1000),
+            max_sims: None,
+            batch_size: 100,
+            policy: Policy::default(),
+            rave: None,
+            workers: 1,
+            merger_queue_bound: 200,
+            merger_batch_size: 100,
+            min_flush_interval: 100,
+            virtual_loss: 1,
+            ponder: false,
+            transpositions: false,
+            memory_limit: None,
+            seed: None,
+        }
+    }
+}
+
+/// A limit on how much a backend's stats take up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemoryLimit {
+    Nodes(usize),
+    Bytes(usize),
+}
+
+impl MemoryLimit {
+    /// The most nodes stats of `size` can keep, going by their average size
+    /// per node.
+    pub fn nodes(&self, size: Size) -> usize {
+        match *self {
+            MemoryLimit::Nodes(n) => n,
+            MemoryLimit::Bytes(b) => {
+                if size.nodes == 0 {
+                    ::std::usize::MAX
+                } else {
+                    b / cmp::max(size.bytes / size.nodes, 1)
+                }
+            }
+        }
+    }
+
+    /// An `n`th share of the limit, for stats split `n` ways.
+    pub fn share(&self, n: usize) -> Self {
+        let n = cmp::max(n, 1);
+        match *self {
+            MemoryLimit::Nodes(nodes) => MemoryLimit::Nodes(nodes / n),
+            MemoryLimit::Bytes(bytes) => MemoryLimit::Bytes(bytes / n),
+        }
+    }
+
+    /// The nodes stats of `size` should be cut down to, if they're over the
+    /// limit.  Cuts go a tenth below the limit, so they don't have to happen
+    /// again straight away.
+    pub fn target(limit: Option<MemoryLimit>, size: Size) -> Option<usize> {
+        limit.and_then(|limit| {
+            let max = limit.nodes(size);
+            if size.nodes > max {
+                Some(max - max / 10)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// How much a backend's stats take up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Size {
+    pub nodes: usize,
+    pub bytes: usize,
+}
+
+impl Size {
+    /// The size of a table of `len` positions and their entries, not
+    /// counting anything the positions keep on the heap.
+    pub fn of_table<G>(len: usize) -> Self {
+        Size {
+            nodes: len,
+            bytes: len * (size_of::<G>() + size_of::<Entry>()),
+        }
+    }
+}
+
+impl Add for Size {
+    type Output = Size;
+    fn add(self, rhs: Self) -> Self::Output {
+        Size {
+            nodes: self.nodes + rhs.nodes,
+            bytes: self.bytes + rhs.bytes,
+        }
+    }
+}
+
+/// Tracks how much of its time and simulation limits a search has used.
+pub struct Budget {
+    deadline: Option<Instant>,
+    sims_left: Option<u64>,
+    batch_size: u64,
+    started: bool,
+    // Set by another thread to end the search early.
+    stop: Option<Arc<AtomicBool>>,
+}
+
+impl Budget {
+    pub fn new(params: &MCTSParams) -> Self {
+        let timeout = match (params.timeout, params.max_sims) {
+            (None, None) => MCTSParams::default().timeout,
+            (timeout, _) => timeout,
+        };
+        Budget {
+            deadline: timeout.map(|t| Instant::now() + Duration::from_millis(t)),
+            sims_left: params.max_sims,
+            batch_size: cmp::max(params.batch_size, 1),
+            started: false,
+            stop: None,
+        }
+    }
+
+    /// A budget with no limits, which runs until `stop` is set.
+    pub fn until_stopped(params: &MCTSParams, stop: Arc<AtomicBool>) -> Self {
+        Budget {
+            deadline: None,
+            sims_left: None,
+            batch_size: cmp::max(params.batch_size, 1),
+            started: false,
+            stop: Some(stop),
+        }
+    }
+
+    /// Shares the budget out between `n` searches running side by side.
+    /// Each gets the same deadline and stop flag and its share of the
+    /// simulations left.
+    pub fn split(&self, n: usize) -> Vec<Budget> {
+        let n = cmp::max(n, 1) as u64;
+        (0..n)
+            .map(|i| Budget {
+                sims_left: self.sims_left.map(|left| left / n + if i < left % n { 1 } else { 0 }),
+                stop: self.stop.clone(),
+                ..*self
+            })
+            .collect()
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    pub fn max_sims(&self) -> Option<u64> {
+        self.sims_left
+    }
+
+    pub fn out_of_time(&self) -> bool {
+        self.deadline.map(|d| Instant::now() >= d).unwrap_or(false)
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stop.as_ref().map(|s| s.load(Ordering::SeqCst)).unwrap_or(false)
+    }
+
+    /// Simulations to run in the next batch, or `None` once either limit is
+    /// reached or the search is stopped.  The first batch always runs, so
+    /// there are stats to pick a move from.
+    pub fn next_batch(&mut self) -> Option<u64> {
+        if self.started && (self.out_of_time() || self.sims_left == Some(0) || self.stopped()) {
+            return None;
+        }
+        self.started = true;
+        let n = match self.sims_left {
+            Some(left) => cmp::min(self.batch_size, left),
+            None => self.batch_size,
+        };
+        self.sims_left = self.sims_left.map(|left| left - n);
+        Some(n)
+    }
+}
+
+/// Read access to the statistics a backend has gathered so far.
+pub trait StatsTable<G> {
+    fn lookup(&self, g: &G) -> Option<Entry>;
+
+    /// Stats of a position, if any simulation has been through it.
+    fn stats(&self, g: &G) -> Option<Stats> {
+        self.lookup(g).map(|e| e.stats).and_then(|s| if s.visits > 0 { Some(s) } else { None })
+    }
+
+    /// Simulations through `g` that other threads haven't finished yet.
+    fn in_flight(&self, _g: &G) -> usize {
+        0
+    }
+
+    /// Called as a simulation walks through `g`, and again once its result
+    /// is in, for tables that count simulations under way.
+    fn enter(&self, _g: &G) {}
+    fn leave(&self, _g: &G) {}
+}
+
+impl<G: Hash + Eq> StatsTable<G> for HashMap<G, Entry> {
+    fn lookup(&self, g: &G) -> Option<Entry> {
+        self.get(g).cloned()
+    }
+}
+
+const IN_FLIGHT_SHARDS: usize = 64;
+
+/// Counts of the simulations under way through each position, split over
+/// several locks so that threads rarely wait on each other.
+pub struct InFlight<G> {
+    shards: Vec<Mutex<HashMap<G, usize>>>,
+}
+
+impl<G: Hash + Eq> Default for InFlight<G> {
+    fn default() -> Self {
+        InFlight {
+            shards: (0..IN_FLIGHT_SHARDS).map(|_| Mutex::new(HashMap::new())).collect(),
+        }
+    }
+}
+
+impl<G: Hash + Eq + Clone> InFlight<G> {
+    fn shard(&self, g: &G) -> &Mutex<HashMap<G, usize>> {
+        let mut hasher = DefaultHasher::new();
+        g.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    pub fn count(&self, g: &G) -> usize {
+        self.shard(g).lock().unwrap().get(g).cloned().unwrap_or(0)
+    }
+
+    pub fn add(&self, g: &G) {
+        *self.shard(g).lock().unwrap().entry(g.clone()).or_insert(0) += 1;
+    }
+
+    pub fn remove(&self, g: &G) {
+        let mut shard = self.shard(g).lock().unwrap();
+        let done = match shard.get_mut(g) {
+            Some(n) => {
+                *n -= 1;
+                *n == 0
+            }
+            None => false,
+        };
+        if done {
+            shard.remove(g);
+        }
+    }
+}
+
+/// A table shared between threads, along with the simulations they have
+/// under way in it.  Simulations stop counting as soon as they finish.
+pub struct InFlightTable<'a, G: 'a, T: 'a> {
+    pub table: &'a T,
+    pub in_flight: &'a InFlight<G>,
+}
+
+impl<'a, G: Hash + Eq + Clone, T: StatsTable<G>> StatsTable<G> for InFlightTable<'a, G, T> {
+    fn lookup(&self, g: &G) -> Option<Entry> {
+        self.table.lookup(g)
+    }
+
+    fn in_flight(&self, g: &G) -> usize {
+        self.in_flight.count(g)
+    }
+
+    fn enter(&self, g: &G) {
+        self.in_flight.add(g);
+    }
+
+    fn leave(&self, g: &G) {
+        self.in_flight.remove(g);
+    }
+}
+
+/// Buffers a simulation reuses from one run to the next.
+pub struct Scratch<G: Game> {
+    moves: Vec<G::Move>,
+    // Every move made in the simulation and who made it, for RAVE.
+    played: Vec<(G::Agent, G::Move)>,
+}
+
+impl<G: Game> Default for Scratch<G> {
+    fn default() -> Self {
+        Scratch {
+            moves: Vec::new(),
+            played: Vec::new(),
+        }
+    }
+}
+
+/// Runs one simulation from `root`.  Walks down through positions that have
+/// stats, choosing moves by the selection policy in `params`, adds the first
+/// position without stats and plays on from there with `rollout`.  Walks stop
+/// early at positions whose result is already proven.  Pushes every position
+/// on the path onto `updates` with the result to credit it with, and any
+/// result the simulation proves.  With RAVE on, also pushes the
+/// all-moves-as-first results for the children of the path that are already
+/// in the table.  Returns the result for the player to act at `root`.
+pub fn simulate<G, T, P, R>(
+    params: &MCTSParams,
+    table: &T,
+    rollout: &mut P,
+    rng: &mut R,
+    root: &G,
+    scratch: &mut Scratch<G>,
+    updates: &mut Vec<(G, Entry)>,
+) -> Value
+where
+    G: RandGame + Eq,
+    T: StatsTable<G>,
+    P: RolloutPolicy<G>,
+    R: Rng,
+{
+    let start = updates.len();
+    let record = params.rave.is_some();
+    let virtual_loss = params.virtual_loss > 0;
+    scratch.played.clear();
+    let mut game = root.clone();
+    // The result, and whose result it is.
+    let (value, seen_by) = loop {
+        let entry = table.lookup(&game).unwrap_or(Entry::ZERO);
+        if virtual_loss {
+            table.enter(&game);
+        }
+        updates.push((game.clone(), Entry::ZERO));
+        if let Some(value) = entry.proven {
+            updates.last_mut().unwrap().1.proven = Some(value);
+            break (value, game.to_act());
+        }
+        game.legal_moves(&mut scratch.moves);
+        if scratch.moves.is_empty() {
+            let value = Value::of(game.outcome(), game.to_act());
+            updates.last_mut().unwrap().1.proven = Some(value);
+            break (value, game.to_act());
+        }
+        if entry.stats.visits == 0 {
+            let played = if record { Some(&mut scratch.played) } else { None };
+            let acting = game.to_act();
+            break (rollout.play_out(game, rng, &mut scratch.moves, played), acting);
+        }
+        let m = select(params, table, rng, &game, &scratch.moves);
+        if record {
+            scratch.played.push((game.to_act(), m));
+        }
+        game.apply(m);
+    };
+
+    let path = updates.len() - start;
+    for update in updates[start..].iter_mut() {
+        if virtual_loss {
+            table.leave(&update.0);
+        }
+        update.1.stats = Stats::single(value.seen_by(seen_by, update.0.to_act()));
+    }
+    prove(table, &mut scratch.moves, &mut updates[start..]);
+    if record {
+        for i in 0..path {
+            let node = updates[start + i].0.clone();
+            amaf_updates(table, &node, &scratch.played[i..], value, seen_by, updates);
+        }
+    }
+    value.seen_by(seen_by, root.to_act())
+}
+
+/// Works up `path` from a proven end, marking each position proven while
+/// the results of its children settle it: a position is a win if any move
+/// wins, and otherwise proven once every move is.
+fn prove<G, T>(table: &T, moves: &mut Vec<G::Move>, path: &mut [(G, Entry)])
+where
+    G: Game + Eq,
+    T: StatsTable<G>,
+{
+    for i in (1..path.len()).rev() {
+        let (child, proven) = match path[i].1.proven {
+            Some(value) => (path[i].0.clone(), value),
+            None => return,
+        };
+        let parent = &path[i - 1].0;
+        let acting = parent.to_act();
+        let mut value = proven.seen_by(child.to_act(), acting);
+        if value != Value::Win {
+            parent.legal_moves(moves);
+            for &m in moves.iter() {
+                let mut g = parent.clone();
+                g.apply(m);
+                let other = if g == child {
+                    Some(proven)
+                } else {
+                    table.lookup(&g).and_then(|e| e.proven)
+                };
+                match other {
+                    Some(v) => value = cmp::max(value, v.seen_by(g.to_act(), acting)),
+                    None => return,
+                }
+            }
+        }
+        path[i - 1].1.proven = Some(value);
+    }
+}
+
+/// Credits each child of `node` reached by a move its player went on to
+/// make in `played`, counting only the first time each move was made.
+fn amaf_updates<G, T>(
+    table: &T,
+    node: &G,
+    played: &[(G::Agent, G::Move)],
+    value: Value,
+    seen_by: G::Agent,
+    updates: &mut Vec<(G, Entry)>,
+) where
+    G: Game,
+    T: StatsTable<G>,
+{
+    let acting = node.to_act();
+    for (j, &(agent, m)) in played.iter().enumerate() {
+        if agent != acting || played[..j].contains(&(agent, m)) || !node.move_valid(&m) {
+            continue;
+        }
+        let mut child = node.clone();
+        child.apply(m);
+        if table.stats(&child).is_some() {
+            let amaf = Stats::single(value.seen_by(seen_by, child.to_act()));
+            updates.push((child, Entry { amaf: amaf, ..Entry::ZERO }));
+        }
+    }
+}
+
+fn select<G, T, R>(params: &MCTSParams, table: &T, rng: &mut R, game: &G, moves: &[G::Move]) -> G::Move
+where
+    G: RandGame,
+    T: StatsTable<G>,
+    R: Rng,
+{
+    let acting = game.to_act();
+    let parent_visits = table.stats(game).map(|s| s.visits).unwrap_or(1)
+        + params.virtual_loss * table.in_flight(game);
+    let uses_priors = params.policy.uses_priors();
+    let total_prior: f64 = if uses_priors {
+        moves.iter().map(|m| game.move_prior(m)).sum()
+    } else {
+        1.0
+    };
+    let candidates = moves.iter().map(|&m| {
+        let mut child = game.clone();
+        child.apply(m);
+        let entry = table.lookup(&child).unwrap_or(Entry::ZERO);
+        let (mut stats, mut amaf) = (entry.stats, entry.amaf);
+        if child.to_act() != acting {
+            stats = stats.flipped();
+            amaf = amaf.flipped();
+        }
+        // Count simulations other threads have under way as losses, so
+        // that they spread out over the tree.
+        let virtual_losses = params.virtual_loss * table.in_flight(&child);
+        stats.losses += virtual_losses;
+        stats.visits += virtual_losses;
+        Candidate {
+            m: m,
+            stats: stats,
+            amaf: amaf,
+            proven: entry.proven.map(|v| v.seen_by(child.to_act(), acting)),
+            prior: if uses_priors {
+                game.move_prior(&m) / total_prior
+            } else {
+                1.0
+            },
+        }
+    });
+    choose(params, rng, parent_visits, candidates).expect("Selecting from a position with no moves.")
+}
+
+/// A move the walk down the tree could take, with what's known about it
+/// from the point of view of the player making it.
+pub struct Candidate<M> {
+    pub m: M,
+    pub stats: Stats,
+    pub amaf: Stats,
+    pub proven: Option<Value>,
+    /// The move's share of the priors of every move from the parent, if the
+    /// policy uses them.
+    pub prior: f64,
+}
+
+/// Picks which of `candidates` to walk down from a parent visited
+/// `parent_visits` times, by the selection policy in `params`.  A proven win
+/// is taken at once, and a proven loss only when nothing else is left.
+pub fn choose<M, R, I>(params: &MCTSParams, rng: &mut R, parent_visits: usize, candidates: I) -> Option<M>
+where
+    R: Rng,
+    I: Iterator<Item = Candidate<M>>,
+{
+    let policy = &params.policy;
+    // Unless the policy scores unvisited children itself, they're picked
+    // uniformly, by reservoir sampling, before any are scored.
+    let mut untried = 0;
+    let mut pick = None;
+    let mut best: Option<(f64, M)> = None;
+    let mut lost = None;
+    for c in candidates {
+        match c.proven {
+            Some(Value::Win) => return Some(c.m),
+            // Only worth walking into when nothing else is left.
+            Some(Value::Loss) => {
+                lost = Some(c.m);
+                continue;
+            }
+            _ => {}
+        }
+        if c.stats.visits == 0 && policy.tries_all_first() {
+            untried += 1;
+            if rng.gen_range(0, untried) == 0 {
+                pick = Some(c.m);
+            }
+            continue;
+        }
+        if untried > 0 {
+            continue;
+        }
+        let mut key = policy.score(rng, c.stats, parent_visits, c.prior);
+        if let Some(rave) = params.rave {
+            key += rave.adjustment(c.stats, c.amaf);
+        }
+        if best.as_ref().map(|&(k, _)| key > k).unwrap_or(true) {
+            best = Some((key, c.m));
+        }
+    }
+    pick.or(best.map(|(_, m)| m)).or(lost)
+}
+
+/// Picks positions for a table of `len` entries to drop to get down to
+/// `target`: leaves of the tree under `root`, the least visited first and,
+/// among those, the furthest from `root`.  Only leaves as they stand are
+/// picked, so it can take more than one round to get down to `target`.
+pub fn evictions<G, T>(table: &T, root: &G, len: usize, target: usize) -> Vec<G>
+where
+    G: Game + Hash + Eq,
+    T: StatsTable<G>,
+{
+    let mut depths = HashMap::new();
+    let mut queue = VecDeque::new();
+    let mut leaves = Vec::new();
+    let mut moves = Vec::new();
+    depths.insert(root.clone(), 0);
+    queue.push_back(root.clone());
+    while let Some(g) = queue.pop_front() {
+        let depth = depths[&g];
+        let mut leaf = true;
+        g.legal_moves(&mut moves);
+        for &m in &moves {
+            let mut child = g.clone();
+            child.apply(m);
+            if table.lookup(&child).is_none() {
+                continue;
+            }
+            leaf = false;
+            if !depths.contains_key(&child) {
+                depths.insert(child.clone(), depth + 1);
+                queue.push_back(child);
+            }
+        }
+        if leaf && depth > 0 {
+            let visits = table.stats(&g).map(|s| s.visits).unwrap_or(0);
+            leaves.push((visits, depth, g));
+        }
+    }
+    leaves.sort_by_key(|&(visits, depth, _)| (visits, cmp::Reverse(depth)));
+    leaves.into_iter().take(len.saturating_sub(target)).map(|(_, _, g)| g).collect()
+}
+
+/// Evicts from `table` down to `target` entries, as far as `evictions`
+/// allows.
+pub fn evict<G: Game + Hash + Eq>(table: &mut HashMap<G, Entry>, root: &G, target: usize) {
+    for g in evictions(table, root, table.len(), target) {
+        table.remove(&g);
+    }
+}
+
+/// Every move from `root` with what `table` knows about it, best first: a
+/// proven win, then the most visited moves that aren't proven losses.
+pub fn rank_moves<G: Game, T: StatsTable<G>>(table: &T, root: &G) -> Vec<RatedMove<G::Move>> {
+    let acting = root.to_act();
+    let mut moves = Vec::new();
+    root.legal_moves(&mut moves);
+    let mut ranked: Vec<_> = moves
+        .into_iter()
+        .map(|m| {
+            let mut g = root.clone();
+            g.apply(m);
+            let entry = table.lookup(&g).unwrap_or(Entry::ZERO);
+            let proven = entry.proven.map(|v| v.seen_by(g.to_act(), acting));
+            let stats = if g.to_act() == acting {
+                entry.stats
+            } else {
+                entry.stats.flipped()
+            };
+            let rating = match proven {
+                Some(value) => Some(Rating::Proven(value)),
+                None if stats.visits > 0 => Some(Rating::WinRate(stats.reward() / stats.visits as f64)),
+                None => None,
+            };
+            let rated = RatedMove {
+                m: m,
+                visits: stats.visits,
+                rating: rating,
+            };
+            ((proven.unwrap_or(Value::Draw), stats.visits), rated)
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.0.cmp(&a.0));
+    ranked.into_iter().map(|(_, rated)| rated).collect()
+}
+
+/// The move to play from `root`, with the stats of the position it leads
+/// to, if it's been visited.
+pub fn best_move<G: Game, T: StatsTable<G>>(table: &T, root: &G) -> Option<(G::Move, Stats)> {
+    rank_moves(table, root).into_iter().next().and_then(|best| {
+        let mut g = root.clone();
+        g.apply(best.m);
+        table.stats(&g).map(|s| (best.m, s))
+    })
+}
+
+/// The line of play `table` expects from `root`: the best move, the best
+/// reply to it and so on, for as far as the moves have been visited.
+pub fn principal_variation<G: Game, T: StatsTable<G>>(table: &T, root: &G) -> Vec<G::Move> {
+    let mut pv = Vec::new();
+    let mut game = root.clone();
+    loop {
+        let best = match rank_moves(table, &game).into_iter().next() {
+            Some(ref best) if best.visits > 0 => best.m,
+            _ => return pv,
+        };
+        pv.push(best);
+        game.apply(best);
+    }
+}
+
+/// Where and how simulations run.  Backends keep the statistics behind
+/// `&self` so they can be shared with threads they start.
+pub trait Backend<G: Game>: StatsTable<G> + Sized {
+    /// How the backend plays out new positions.
+    type Rollout;
+
+    fn create(params: MCTSParams, rollout: Self::Rollout) -> Self;
+
+    /// Runs simulations from `root` until `budget` runs out.
+    fn search(&self, root: &G, budget: Budget);
+
+    /// Forgets every position that can't come up from `root`.
+    fn prune(&self, root: &G);
+
+    /// How much the stats take up.
+    fn size(&self) -> Size;
+
+    /// Stops any threads the backend runs and waits for them.
+    fn shutdown(&self) {}
+}
+
+/// Monte Carlo tree search, with the simulations run by `B`.
+pub struct MCTS<G: Game, B: Backend<G>> {
+    params: MCTSParams,
+    backend: Arc<B>,
+    // The flag that stops the search running on the opponent's time, and
+    // the thread running it.
+    pondering: Option<(Arc<AtomicBool>, thread::JoinHandle<()>)>,
+    game: PhantomData<G>,
+}
+
+impl<G: Game, B: Backend<G>> MCTS<G, B> {
+    /// A search that plays out new positions with `rollout` rather than the
+    /// backend's default.
+    pub fn with_rollout(params: MCTSParams, rollout: B::Rollout) -> Self {
+        MCTS {
+            params: params,
+            backend: Arc::new(B::create(params, rollout)),
+            pondering: None,
+            game: PhantomData,
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn is_pondering(&self) -> bool {
+        self.pondering.is_some()
+    }
+
+    /// Stops searching on the opponent's time and waits for the search to
+    /// hand in what it found.
+    pub fn stop_pondering(&mut self) {
+        if let Some((stop, handle)) = self.pondering.take() {
+            stop.store(true, Ordering::SeqCst);
+            handle.join().expect("Pondering panicked.");
+        }
+    }
+
+    /// Stops any threads the search runs and waits for them to finish.  The
+    /// strategy can't decide anything afterwards.  Dropping it does the same.
+    pub fn shutdown(&mut self) {
+        self.stop_pondering();
+        self.backend.shutdown();
+    }
+}
+
+impl<G: Game, B: Backend<G>> Drop for MCTS<G, B> {
+    fn drop(&mut self) {
+        self.stop_pondering();
+    }
+}
+
+impl<G, B> Strategy<G> for MCTS<G, B>
+where
+    G: RandGame + 'static,
+    B: Backend<G> + Send + Sync + 'static,
+    B::Rollout: Default,
+{
+    type Params = MCTSParams;
+
+    fn analyze(&mut self, game: &G) -> Analysis<G> {
+        let start = Instant::now();
+        // Keep what we learned about positions still ahead of us, from both
+        // our last move and the opponent's reply, and while they thought.
+        self.stop_pondering();
+        self.backend.prune(game);
+        let visits = |backend: &B| backend.stats(game).map(|s| s.visits).unwrap_or(0);
+        let before = visits(&self.backend);
+        self.backend.search(game, Budget::new(&self.params));
+
+        Analysis {
+            moves: rank_moves(&*self.backend, game),
+            pv: principal_variation(&*self.backend, game),
+            nodes: visits(&self.backend).saturating_sub(before),
+            elapsed: start.elapsed(),
+        }
+    }
+
+    fn ponder(&mut self, game: &G) {
+        self.stop_pondering();
+        if !self.params.ponder || game.is_over() {
+            return;
+        }
+        self.backend.prune(game);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let budget = Budget::until_stopped(&self.params, stop.clone());
+        let backend = self.backend.clone();
+        let game = game.clone();
+        let handle = thread::spawn(move || backend.search(&game, budget));
+        self.pondering = Some((stop, handle));
+    }
+
+    fn create(params: MCTSParams) -> Self {
+        MCTS::with_rollout(params, B::Rollout::default())
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use game::connectfour::{Color, ConnectFour};
+    use rand::{SeedableRng, XorShiftRng};
+    use strategies::rollout::Uniform;
+
+    fn params() -> MCTSParams {
+        MCTSParams {
+            batch_size: 4,
+            ..sim_params(10)
+        }
+    }
+
+    /// Plays the columns in `moves` from `game`, each for whoever is to act.
+    pub fn play_moves(game: &ConnectFour, moves: &[usize]) -> ConnectFour {
+        let mut game = game.clone();
+        for &n in moves {
+            let m = (n, game.to_act());
+            assert!(game.try_move(m));
+        }
+        game
+    }
+
+    /// Params for a search of `max_sims` simulations, with no timeout.
+    pub fn sim_params(max_sims: u64) -> MCTSParams {
+        MCTSParams {
+            timeout: None,
+            max_sims: Some(max_sims),
+            ..Default::default()
+        }
+    }
+
+    /// The table `n` seeded simulations from `game` build up, one after
+    /// another.
+    pub fn run_sims(params: &MCTSParams, game: &ConnectFour, n: usize) -> HashMap<ConnectFour, Entry> {
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        let (mut scratch, mut updates) = (Scratch::default(), Vec::new());
+        let mut table = HashMap::new();
+        for _ in 0..n {
+            simulate(params, &table, &mut Uniform, &mut rng, game, &mut scratch, &mut updates);
+            for (g, s) in updates.drain(..) {
+                *table.entry(g).or_insert(Entry::ZERO) += s;
+            }
+        }
+        table
+    }
+
+    /// Params for the multi-threaded backends' tests: two workers, sharing
+    /// what they find as often as they can.
+    pub fn parallel_params(timeout: Option<u64>, max_sims: Option<u64>) -> MCTSParams {
+        MCTSParams {
+            timeout: timeout,
+            max_sims: max_sims,
+            workers: 2,
+            merger_queue_bound: 10,
+            merger_batch_size: 1,
+            min_flush_interval: 1,
+            ..Default::default()
+        }
+    }
+
+    /// Checks that a search with backend `B` runs exactly `sims`
+    /// simulations when given that budget.
+    pub fn assert_stops_at_sim_limit<B>(sims: u64)
+    where
+        B: Backend<ConnectFour> + Send + Sync + 'static,
+        B::Rollout: Default,
+    {
+        let game = ConnectFour::new(&Color::R);
+        let mut strategy = MCTS::<ConnectFour, B>::create(parallel_params(None, Some(sims)));
+        strategy.decide(&game);
+        // Every simulation passes through the root.
+        assert_eq!(strategy.backend().stats(&game).map(|s| s.visits), Some(sims as usize));
+    }
+
+    #[test]
+    fn test_budget_batches() {
+        let mut budget = Budget::new(&params());
+        let batches: Vec<_> = iter::repeat(()).map(|_| budget.next_batch()).take(5).collect();
+        assert_eq!(batches, vec![Some(4), Some(4), Some(2), None, None]);
+    }
+
+    #[test]
+    fn test_budget_without_limits_uses_default_timeout() {
+        let start = Instant::now();
+        let budget = Budget::new(&MCTSParams {
+            timeout: None,
+            max_sims: None,
+            ..Default::default()
+        });
+        let timeout = Duration::from_millis(MCTSParams::default().timeout.unwrap());
+        assert!(budget.deadline().unwrap() >= start + timeout);
+        assert!(budget.deadline().unwrap() <= Instant::now() + timeout);
+        assert_eq!(budget.max_sims(), None);
+    }
+
+    #[test]
+    fn test_simulate_adds_one_position() {
+        let game = ConnectFour::new(&Color::R);
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        let (mut scratch, mut updates) = (Scratch::default(), Vec::new());
+        let mut table = HashMap::new();
+        for i in 0..10 {
+            simulate(&params(), &table, &mut Uniform, &mut rng, &game, &mut scratch, &mut updates);
+            // The root, then one new position under it each time until all
+            // seven are tried, then one a level further down.
+            assert_eq!(table.len(), i);
+            assert_eq!(updates.len(), if i == 0 { 1 } else if i <= 7 { 2 } else { 3 });
+            for (g, s) in updates.drain(..) {
+                *table.entry(g).or_insert(Entry::ZERO) += s;
+            }
+        }
+        assert_eq!(table[&game].stats.visits, 10);
+        let (_, best) = best_move(&table, &game).unwrap();
+        assert!(best.visits >= 2);
+    }
+
+    #[test]
+    fn test_proves_a_win() {
+        let game = play_moves(&ConnectFour::new(&Color::R), &[0, 6, 1, 6, 2, 5]);
+        let table = run_sims(&params(), &game, 20);
+        // Once the winning move has been tried, the root is a proven win and
+        // every later simulation stops there.
+        assert_eq!(table[&game].proven, Some(Value::Win));
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        let (mut scratch, mut updates) = (Scratch::default(), Vec::new());
+        assert_eq!(
+            simulate(&params(), &table, &mut Uniform, &mut rng, &game, &mut scratch, &mut updates),
+            Value::Win
+        );
+        assert_eq!(updates.len(), 1);
+        let (m, _) = best_move(&table, &game).unwrap();
+        assert_eq!(m, (3, Color::R));
+    }
+
+    #[test]
+    fn test_takes_proven_win_over_untried_moves() {
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        let candidate = |m, stats, proven| {
+            Candidate {
+                m: m,
+                stats: stats,
+                amaf: Stats::ZERO,
+                proven: proven,
+                prior: 1.0,
+            }
+        };
+        // The win comes after a move that hasn't been tried yet.
+        for _ in 0..10 {
+            let candidates = vec![
+                candidate(0, Stats::ZERO, None),
+                candidate(1, Stats::single(Value::Loss), Some(Value::Loss)),
+                candidate(2, Stats::single(Value::Win), Some(Value::Win)),
+                candidate(3, Stats::ZERO, None),
+            ];
+            assert_eq!(choose(&params(), &mut rng, 2, candidates.into_iter()), Some(2));
+        }
+    }
+
+    #[test]
+    fn test_virtual_loss_spreads_selection() {
+        let game = ConnectFour::new(&Color::R);
+        let table = run_sims(&params(), &game, 100);
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        let mut moves = Vec::new();
+        game.legal_moves(&mut moves);
+        let in_flight = InFlight::default();
+        let shared = InFlightTable {
+            table: &table,
+            in_flight: &in_flight,
+        };
+        let first = select(&params(), &shared, &mut rng, &game, &moves);
+        let mut child = game.clone();
+        child.apply(first);
+        for _ in 0..100 {
+            shared.enter(&child);
+        }
+        assert_eq!(shared.in_flight(&child), 100);
+        assert!(select(&params(), &shared, &mut rng, &game, &moves) != first);
+
+        for _ in 0..100 {
+            shared.leave(&child);
+        }
+        assert_eq!(select(&params(), &shared, &mut rng, &game, &moves), first);
+        assert!(in_flight.shards.iter().all(|s| s.lock().unwrap().is_empty()));
+    }
+
+    #[test]
+    fn test_rave_credits_later_moves() {
+        let game = ConnectFour::new(&Color::R);
+        let params = MCTSParams {
+            rave: Some(Rave::Equivalence(100.0)),
+            ..params()
+        };
+        let table = run_sims(&params, &game, 200);
+        // Every simulation through a child after it was added counts for its
+        // AMAF stats, along with ones that made the move later.
+        let mut moves = Vec::new();
+        game.legal_moves(&mut moves);
+        for m in moves {
+            let mut child = game.clone();
+            child.apply(m);
+            let entry = table[&child];
+            assert!(entry.amaf.visits + 1 >= entry.stats.visits);
+            assert!(entry.amaf.visits > entry.stats.visits);
+        }
+    }
+
+    #[test]
+    fn test_evicts_low_visit_leaves() {
+        let game = ConnectFour::new(&Color::R);
+        let mut table = run_sims(&params(), &game, 300);
+        let children: Vec<_> = (0..7)
+            .map(|n| {
+                let mut g = game.clone();
+                g.apply((n, Color::R));
+                g
+            })
+            .collect();
+        let least = |table: &HashMap<ConnectFour, Entry>| table.values().map(|e| e.stats.visits).min();
+        assert_eq!(least(&table), Some(1));
+
+        evict(&mut table, &game, 250);
+        assert_eq!(table.len(), 250);
+        // The most visited positions stay.
+        assert!(table.contains_key(&game));
+        assert!(children.iter().all(|g| table.contains_key(g)));
+
+        let limit = Some(MemoryLimit::Nodes(100));
+        let size = Size::of_table::<ConnectFour>(table.len());
+        assert_eq!(MemoryLimit::target(limit, size), Some(90));
+        assert_eq!(MemoryLimit::target(limit, Size::of_table::<ConnectFour>(100)), None);
+        let bytes = Some(MemoryLimit::Bytes(size.bytes / 2));
+        assert_eq!(MemoryLimit::target(bytes, size), Some(113));
+    }
+}
@@ -0,0 +1,209 @@
+use super::mcts_core::{self, Backend, Budget, Entry, MemoryLimit, Scratch, Size, StatsTable};
+use super::rollout::{RolloutPolicy, Uniform};
+use game::*;
+use rand::XorShiftRng;
+use std::cmp;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::*;
+use crossbeam;
+
+pub use super::mcts_core::MCTSParams;
+
+/// Root-parallel MCTS: independent searches, each playing out new positions
+/// with its own copy of a `P`, whose results are only added up at the end.
+pub type MCTS<G, P = Uniform> = mcts_core::MCTS<G, Ensemble<G, P>>;
+
+struct Member<G: Game, P> {
+    rollout: P,
+    rng: XorShiftRng,
+    scratch: Scratch<G>,
+    updates: Vec<(G, Entry)>,
+}
+
+impl<G: RandGame + Hash + Eq, P: RolloutPolicy<G>> Member<G, P> {
+    // Writes into `stats` a simulation at a time, so they can be read in
+    // between.
+    fn search(
+        &mut self,
+        params: &MCTSParams,
+        stats: &RwLock<HashMap<G, Entry>>,
+        root: &G,
+        mut budget: Budget,
+        limit: Option<MemoryLimit>,
+    ) {
+        while let Some(n) = budget.next_batch() {
+            for _ in 0..n {
+                mcts_core::simulate(
+                    params,
+                    &*stats.read().expect("Lock poisoned"),
+                    &mut self.rollout,
+                    &mut self.rng,
+                    root,
+                    &mut self.scratch,
+                    &mut self.updates,
+                );
+                let mut stats = stats.write().expect("Lock poisoned");
+                for (g, s) in self.updates.drain(..) {
+                    *stats.entry(g).or_insert(Entry::ZERO) += s;
+                }
+            }
+            let mut stats = stats.write().expect("Lock poisoned");
+            if let Some(target) = MemoryLimit::target(limit, Size::of_table::<G>(stats.len())) {
+                mcts_core::evict(&mut *stats, root, target);
+            }
+        }
+    }
+}
+
+/// Runs `workers` searches on threads of their own, each with its own random
+/// number stream (drawn from `params.seed`, if set) and table, sharing nothing
+/// while they run.  A position's stats are the sum over every search, so the
+/// move picked is the one with the most visits across all of them.  Nothing
+/// is shared on the hot path, which makes it a baseline for the backends that
+/// share a tree.  Each search gets an equal share of the memory limit.
+pub struct Ensemble<G: Game, P = Uniform> {
+    params: MCTSParams,
+    // Each lock is only taken by its own search while one runs.
+    members: Vec<Mutex<Member<G, P>>>,
+    // Each search's stats, only locked while a simulation's results are
+    // written in, so that they can be looked up while it runs.
+    tables: Vec<RwLock<HashMap<G, Entry>>>,
+}
+
+impl<G: Game + Hash + Eq, P> StatsTable<G> for Ensemble<G, P> {
+    fn lookup(&self, g: &G) -> Option<Entry> {
+        let entries: Vec<Entry> = self.tables
+            .iter()
+            .flat_map(|t| t.read().expect("Lock poisoned").lookup(g))
+            .collect();
+        if entries.is_empty() {
+            None
+        } else {
+            Some(entries.iter().sum())
+        }
+    }
+}
+
+impl<G, P> Backend<G> for Ensemble<G, P>
+where
+    G: RandGame + Hash + Eq + Send + Sync,
+    P: RolloutPolicy<G> + Clone + Send,
+{
+    type Rollout = P;
+
+    fn create(params: MCTSParams, rollout: P) -> Self {
+        let members = (0..cmp::max(params.workers, 1))
+            .map(|i| {
+                Mutex::new(Member {
+                    rollout: rollout.clone(),
+                    rng: seeded_rng(params.seed, i),
+                    scratch: Scratch::default(),
+                    updates: Vec::new(),
+                })
+            })
+            .collect::<Vec<_>>();
+        Ensemble {
+            params: params,
+            tables: members.iter().map(|_| RwLock::new(HashMap::new())).collect(),
+            members: members,
+        }
+    }
+
+    fn search(&self, root: &G, budget: Budget) {
+        let params = &self.params;
+        let budgets = budget.split(self.members.len());
+        let limit = params.memory_limit.map(|l| l.share(self.members.len()));
+        let searches = self.members.iter().zip(&self.tables).zip(budgets);
+        crossbeam::scope(|scope| for ((member, stats), budget) in searches {
+            scope.spawn(move || {
+                member.lock().expect("Lock poisoned").search(params, stats, root, budget, limit)
+            });
+        });
+    }
+
+    fn prune(&self, root: &G) {
+        for stats in &self.tables {
+            stats.write().expect("Lock poisoned").retain(|k, _| root.reachable(k));
+        }
+    }
+
+    fn size(&self) -> Size {
+        self.tables.iter().fold(Size::default(), |size, t| {
+            size + Size::of_table::<G>(t.read().expect("Lock poisoned").len())
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::Strategy;
+    use game::connectfour::{Color, ConnectFour};
+    use strategies::mcts_core::test::sim_params;
+    use std::thread;
+
+    #[test]
+    fn test_adds_up_independent_searches() {
+        let game = ConnectFour::new(&Color::R);
+        let mut strategy = MCTS::<ConnectFour>::create(MCTSParams {
+            workers: 3,
+            ..sim_params(1000)
+        });
+        let m = strategy.decide(&game);
+        assert!(game.move_valid(&m));
+
+        // The simulations are shared out between the searches, and each
+        // kept its own table.
+        let backend = strategy.backend();
+        let visits: Vec<_> = backend
+            .tables
+            .iter()
+            .map(|t| t.read().unwrap()[&game].stats.visits)
+            .collect();
+        assert_eq!(visits, vec![334, 333, 333]);
+        assert_eq!(backend.stats(&game).map(|s| s.visits), Some(1000));
+
+        // The move picked has the most visits summed over every search.
+        let mut moves = Vec::new();
+        game.legal_moves(&mut moves);
+        let visits = |m| {
+            let mut g = game.clone();
+            g.apply(m);
+            backend.stats(&g).map(|s| s.visits).unwrap_or(0)
+        };
+        assert!(moves.iter().all(|&other| visits(other) <= visits(m)));
+    }
+
+    #[test]
+    fn test_looks_up_stats_while_searching() {
+        let game = ConnectFour::new(&Color::R);
+        let mut strategy = MCTS::<ConnectFour>::create(MCTSParams {
+            ponder: true,
+            workers: 2,
+            ..Default::default()
+        });
+        strategy.ponder(&game);
+        // Each search holds its own lock until it's stopped, but what it has
+        // found so far can still be read.
+        while strategy.backend().stats(&game).is_none() {
+            thread::yield_now();
+        }
+        assert!(strategy.is_pondering());
+        strategy.stop_pondering();
+    }
+
+    #[test]
+    fn test_seed_reproduces_search() {
+        let game = ConnectFour::new(&Color::R);
+        let analyze = || {
+            let mut strategy = MCTS::<ConnectFour>::create(MCTSParams {
+                workers: 3,
+                seed: Some([1, 2, 3, 4]),
+                ..sim_params(600)
+            });
+            strategy.analyze(&game).moves
+        };
+        assert_eq!(analyze(), analyze());
+    }
+}
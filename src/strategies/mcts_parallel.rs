@@ -1,393 +1,579 @@
-use super::*;
+use super::mcts_core::{self, Backend, Budget, Entry, InFlight, InFlightTable, MemoryLimit, Scratch, Size, StatsTable};
+use super::rollout::{RolloutPolicy, Uniform};
 use game::*;
-use std::mem::size_of;
 use rand::XorShiftRng;
-use rand;
-use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
 use std::sync::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
 use std::time::{Instant, Duration};
 
-#[derive(Debug, Clone)]
-struct Stats {
-    wins: usize,
-    losses: usize,
-    visits: usize,
-}
-
 use std::collections::HashMap;
 use std::hash::Hash;
-#[derive(Clone, Copy)]
-pub struct MCTSParams {
-    // Time limit in ms.
-    pub timeout: u64,
-    pub c: f64,
-    pub workers: u64,
-    pub worker_batch_size: u64,
-    pub merger_queue_bound: usize,
-    pub merger_batch_size: u64,
-    pub min_flush_interval: u64,
-}
 
-// struct State<G: Hash + PartialEq + Eq> {
-//     stats: HashMap<G, Stats>,
-//     cur: Option<G>,
-// }
+pub use super::mcts_core::MCTSParams;
+
+/// MCTS run by worker threads that share their results through a merger,
+/// each playing out new positions with its own copy of a `P`.
+pub type MCTS<G, P = Uniform> = mcts_core::MCTS<G, Workers<G, P>>;
 
 enum WorkerMessage<G: Send> {
-    UpdateStats(HashMap<G, Stats>),
+    UpdateStats(HashMap<G, Entry>),
     UpdateCur(Option<G>),
+    // Drop whatever can't come up from this position any more.
+    Prune(G),
+    // Hand in any updates, stop searching and say so.
+    Pause(mpsc::Sender<()>),
+    // Run this many simulations from the position and hand back what they
+    // found, for seeded searches.
+    Round(G, u64, mpsc::Sender<(usize, HashMap<G, Entry>)>),
+    // What the other workers found in the last round.
+    Share(G, HashMap<G, Entry>),
+    Stop,
 }
 
-struct MCTSWorker<G: Sync + Hash + Eq + RandGame + 'static> {
+struct MCTSWorker<G: Game + Hash + Eq + 'static, P> {
+    id: usize,
     input: mpsc::Receiver<WorkerMessage<G>>,
+    rollout: P,
+    rng: XorShiftRng,
+    scratch: Scratch<G>,
+    path: Vec<(G, Entry)>,
     last_flush: Instant,
     merger: mpsc::SyncSender<MergerMessage<G>>,
     params: MCTSParams,
-    updates: HashMap<G, Stats>,
-    stats_cache: HashMap<G, Stats>,
+    updates: HashMap<G, Entry>,
+    stats_cache: HashMap<G, Entry>,
     cur: Option<G>,
+    // Simulations left in the current search, shared by every worker.
+    remaining: Arc<AtomicUsize>,
+    // Simulations every worker has under way, for virtual loss.
+    in_flight: Arc<InFlight<G>>,
 }
 
 enum MergerMessage<G> {
-    GetStats(mpsc::Sender<HashMap<G, Stats>>),
+    GetStats(mpsc::Sender<HashMap<G, Entry>>),
     Prune(G),
-    Merge(HashMap<G, Stats>),
+    Merge(HashMap<G, Entry>),
+    Stop,
 }
 
 struct MCTSMerger<G: Send> {
     input: mpsc::Receiver<MergerMessage<G>>,
-    priority_input: mpsc::Receiver<MergerMessage<G>>,
     worker_outputs: Vec<mpsc::Sender<WorkerMessage<G>>>,
     params: MCTSParams,
-    stats: HashMap<G, Stats>,
+    stats: HashMap<G, Entry>,
+    root: Option<G>,
+    merges: u64,
 }
 
 impl<G: 'static + Send + Clone + Game + Eq + Hash> MCTSMerger<G> {
-    fn new(
-        params: MCTSParams,
-        outputs: Vec<mpsc::Sender<WorkerMessage<G>>>,
-        input: mpsc::Receiver<MergerMessage<G>>,
-        priority_input: mpsc::Receiver<MergerMessage<G>>,
-    ) -> Self {
-        let new = MCTSMerger {
-            params: params,
-            input: input,
-            priority_input: priority_input,
-            stats: HashMap::new(),
-            worker_outputs: outputs,
-        };
-        new
-    }
-
-    fn start(mut self) {
-        thread::spawn(move || loop {
-            for i in 0..self.params.merger_batch_size {
-                if i % 50 == 0 {
-                    if let Ok(msg) = self.priority_input.try_recv() {
-                        self.handle(msg);
-                    }
-                }
-                let msg = self.input.recv().expect("Recv failed.");
-                self.handle(msg);
+    // Messages are handled in the order they're sent, so stats asked for
+    // after a worker has flushed include its updates.
+    fn start(mut self) -> thread::JoinHandle<()> {
+        thread::spawn(move || while let Ok(msg) = self.input.recv() {
+            if !self.handle(msg) {
+                return;
             }
+        })
+    }
 
-            for tx in &self.worker_outputs {
-                tx.send(WorkerMessage::UpdateStats(self.stats.clone()))
-                    .expect("UpdateStats failed.");
-            }
-        });
+    fn send_stats(&self) {
+        // A worker that has already stopped doesn't need them.
+        for tx in &self.worker_outputs {
+            let _ = tx.send(WorkerMessage::UpdateStats(self.stats.clone()));
+        }
     }
 
     fn prune(&mut self, g: &G) {
-        println!(
-            "PRUNING BEFORE: - size: {}, bytes: {}KB",
-            self.stats.len(),
-            (self.stats.len() * (size_of::<G>() + size_of::<Stats>())) / 1000
-        );
-        self.stats.retain(|k, v| g.reachable(k) && v.visits > 5);
-        println!(
-            "PRUNING AFTER: - size: {}, bytes: {}KB",
-            self.stats.len(),
-            (self.stats.len() * (size_of::<G>() + size_of::<Stats>())) / 1000
-        );
+        self.stats.retain(|k, _| g.reachable(k));
+        self.root = Some(g.clone());
     }
 
-    fn handle(&mut self, msg: MergerMessage<G>) {
+    // Returns whether to keep running.
+    fn handle(&mut self, msg: MergerMessage<G>) -> bool {
         use self::MergerMessage::*;
         match msg {
             GetStats(tx) => {
                 tx.send(self.stats.clone()).expect("GetStats failed.");
-
             }
             Prune(cur) => {
                 self.prune(&cur);
             }
             Merge(updates) => {
-                for (k, v) in updates.into_iter() {
-                    let mut stats = self.stats.entry(k).or_insert(Stats {
-                        visits: 0,
-                        wins: 0,
-                        losses: 0,
-                    });
-
-                    stats.visits += v.visits;
-                    stats.wins += v.wins;
-                    stats.losses += v.losses;
+                // Updates queued from before the last prune can be for
+                // positions that are already out of reach.
+                let root = self.root.clone();
+                let reachable = |k: &G| root.as_ref().map(|r| r.reachable(k)).unwrap_or(true);
+                for (k, v) in updates.into_iter().filter(|&(ref k, _)| reachable(k)) {
+                    *self.stats.entry(k).or_insert(Entry::ZERO) += v;
+                }
+                let size = Size::of_table::<G>(self.stats.len());
+                if let (Some(target), Some(root)) = (MemoryLimit::target(self.params.memory_limit, size), root.as_ref()) {
+                    mcts_core::evict(&mut self.stats, root, target);
                 }
-            }
-        }
-
-    }
-}
-
-pub struct MCTS<G: Sync + Hash + Eq + RandGame + 'static> {
-    params: MCTSParams,
-    workers: Vec<mpsc::Sender<WorkerMessage<G>>>,
-    merger: mpsc::Sender<MergerMessage<G>>,
-    // merger: mpsc::Sender
-}
-
-impl<G: RandGame + Eq + Hash + Sync + 'static> MCTSWorker<G> {
-    fn simulate<R: rand::Rng>(&mut self, rng: &mut R, game: &G) -> Option<G::Agent> {
-        let acting = game.to_act();
-        let nexts = game.possible_moves();
-        let winner = if nexts.is_empty() {
-            game.winner()
-        } else {
-            let parent_visits = {
-                self.stats_cache.get(game).map(|s| s.visits).unwrap_or(1)
-            };
-            let g = self.select(rng, nexts, parent_visits, acting);
-            self.simulate(rng, &g)
-        };
-
-        let stats = self.stats_cache.entry(game.clone()).or_insert(Stats {
-            wins: 0,
-            losses: 0,
-            visits: 0,
-        });
-
-        stats.visits += 1;
-        if let Some(w) = winner {
-            if w == acting {
-                stats.wins += 1;
-            } else {
-                stats.losses += 1;
-            }
-        }
-
-        let stats = self.updates.entry(game.clone()).or_insert(Stats {
-            wins: 0,
-            losses: 0,
-            visits: 0,
-        });
 
-        stats.visits += 1;
-        if let Some(w) = winner {
-            if w == acting {
-                stats.wins += 1;
-            } else {
-                stats.losses += 1;
+                // Seeded workers keep their copies in step themselves.
+                self.merges += 1;
+                if self.params.seed.is_none() && self.merges % self.params.merger_batch_size.max(1) == 0 {
+                    self.send_stats();
+                }
             }
+            Stop => return false,
         }
-
-        winner
+        true
     }
+}
 
-    fn key(&self, (g, s): (&G, &Stats), n: f64, acting: G::Agent) -> f64 {
-        let wins = if acting == g.to_act() {
-            s.wins
-        } else {
-            s.losses
-        } as f64;
-        let visits = s.visits as f64;
-        wins / visits + self.params.c * (n.ln() / visits).sqrt()
-    }
-
-    fn select<R: rand::Rng>(
-        &self,
-        rng: &mut R,
-        choices: Vec<ValidMove<G>>,
-        parent_visits: usize,
-        acting: G::Agent,
-    ) -> G {
-        let n = choices.len();
-
-        if choices.is_empty() {
-            panic!("Shouldn't have gotten here.")
-        }
-
-        let i = rng.gen::<usize>();
-        let random_choice = choices[i % n].clone().apply();
-        let games: Vec<_> = choices
-            .into_iter()
-            .flat_map(|m| {
-                let game = m.apply();
-                let stats = self.stats_cache.get(&game);
-                stats.map(|s| (game, s))
-            })
-            .collect();
-
-        if games.len() == n {
-            let x = games.into_iter().max_by(|t1, t2| {
-                let t1 = (&t1.0, t1.1);
-                let t2 = (&t2.0, t2.1);
-                self.key(t1, parent_visits as f64, acting)
-                    .partial_cmp(&self.key(t2, parent_visits as f64, acting))
-                    .expect("2")
-            });
-            x.expect("1").0
-        } else {
-            random_choice
+impl<G: RandGame + Eq + Hash + 'static, P: RolloutPolicy<G> + Send + 'static> MCTSWorker<G, P> {
+    fn new(
+        id: usize,
+        input: mpsc::Receiver<WorkerMessage<G>>,
+        merger: mpsc::SyncSender<MergerMessage<G>>,
+        params: MCTSParams,
+        rollout: P,
+        remaining: Arc<AtomicUsize>,
+        in_flight: Arc<InFlight<G>>,
+    ) -> Self {
+        MCTSWorker {
+            id: id,
+            input: input,
+            rollout: rollout,
+            rng: seeded_rng(params.seed, id as u64),
+            scratch: Scratch::default(),
+            path: Vec::new(),
+            last_flush: Instant::now(),
+            merger: merger,
+            params: params,
+            updates: HashMap::new(),
+            stats_cache: HashMap::new(),
+            cur: None,
+            remaining: remaining,
+            in_flight: in_flight,
         }
     }
 
-    fn handle(&mut self, msg: WorkerMessage<G>) {
+    // Returns whether to keep running.
+    fn handle(&mut self, msg: WorkerMessage<G>) -> bool {
         use self::WorkerMessage::*;
         match msg {
             UpdateStats(stats) => {
-                // self.flush_updates();
                 self.stats_cache = stats;
             }
             UpdateCur(cur) => self.cur = cur,
+            Prune(root) => {
+                self.stats_cache.retain(|k, _| root.reachable(k));
+                self.updates.retain(|k, _| root.reachable(k));
+            }
+            Pause(done) => {
+                self.flush_updates();
+                self.cur = None;
+                done.send(()).expect("Pause failed.");
+            }
+            Round(root, sims, done) => {
+                for _ in 0..sims {
+                    self.simulate(&root);
+                }
+                let updates = mem::replace(&mut self.updates, HashMap::new());
+                done.send((self.id, updates)).expect("Round failed.");
+            }
+            Share(root, others) => {
+                for (g, s) in others {
+                    *self.stats_cache.entry(g).or_insert(Entry::ZERO) += s;
+                }
+                // Every worker's copy is the same, so they all evict the same.
+                let size = Size::of_table::<G>(self.stats_cache.len());
+                if let Some(target) = MemoryLimit::target(self.params.memory_limit, size) {
+                    mcts_core::evict(&mut self.stats_cache, &root, target);
+                }
+            }
+            Stop => return false,
         }
+        true
     }
 
-    fn maybe_flush_updates(&mut self) {
-        use std::mem;
-        let now = Instant::now();
-        if now.duration_since(self.last_flush) >
-            Duration::from_millis(self.params.min_flush_interval)
-        {
+    // Takes one of the simulations left, if there are any.
+    fn claim(&self) -> bool {
+        let mut left = self.remaining.load(Ordering::SeqCst);
+        loop {
+            if left == 0 {
+                return false;
+            }
+            match self.remaining.compare_exchange(left, left - 1, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => return true,
+                Err(now) => left = now,
+            }
+        }
+    }
+
+    fn flush_updates(&mut self) {
+        if !self.updates.is_empty() {
             self.merger
                 .send(MergerMessage::Merge(
                     mem::replace(&mut self.updates, HashMap::new()),
                 ))
                 .expect("Couldn't flush updates.");
-            self.last_flush = now;
+        }
+        self.last_flush = Instant::now();
+    }
+
+    fn maybe_flush_updates(&mut self) {
+        if self.last_flush.elapsed() > Duration::from_millis(self.params.min_flush_interval) {
+            self.flush_updates();
+        }
+    }
+
+    // Runs a simulation from `game` and adds it to the cache and updates.
+    fn simulate(&mut self, game: &G) {
+        {
+            let table = InFlightTable {
+                table: &self.stats_cache,
+                in_flight: &*self.in_flight,
+            };
+            mcts_core::simulate(
+                &self.params,
+                &table,
+                &mut self.rollout,
+                &mut self.rng,
+                game,
+                &mut self.scratch,
+                &mut self.path,
+            );
+        }
+        for (g, s) in self.path.drain(..) {
+            *self.stats_cache.entry(g.clone()).or_insert(Entry::ZERO) += s;
+            *self.updates.entry(g).or_insert(Entry::ZERO) += s;
         }
     }
 
-    fn start(mut self) {
+    // Runs until told to stop or the backend goes away.
+    fn start(mut self) -> thread::JoinHandle<()> {
         thread::spawn(move || {
-            let seed = rand::random::<[u32; 4]>();
-            let mut rng: XorShiftRng = rand::SeedableRng::from_seed(seed);
             loop {
-                for _ in 0..self.params.worker_batch_size {
-                    if let Ok(msg) = self.input.try_recv() {
-                        self.handle(msg);
+                let msg = if self.cur.is_none() {
+                    // Nothing to search, so sleep until there is.
+                    match self.input.recv() {
+                        Ok(msg) => Some(msg),
+                        Err(_) => return,
+                    }
+                } else {
+                    match self.input.try_recv() {
+                        Ok(msg) => Some(msg),
+                        Err(mpsc::TryRecvError::Empty) => None,
+                        Err(mpsc::TryRecvError::Disconnected) => return,
                     }
+                };
+                if let Some(msg) = msg {
+                    if !self.handle(msg) {
+                        return;
+                    }
+                    continue;
+                }
 
-                    let game = {
-                        match self.cur {
-                            None => continue,
-                            Some(ref g) => g.clone(),
-                        }
-                    };
-                    self.simulate(&mut rng, &game);
+                if !self.claim() {
+                    // The search is out of simulations.
+                    self.flush_updates();
+                    self.cur = None;
+                    continue;
                 }
 
+                let game = self.cur.clone().expect("Searching without a position.");
+                self.simulate(&game);
                 self.maybe_flush_updates();
             }
-        });
+        })
     }
 }
 
-impl<G> MCTS<G>
-where
-    G: RandGame + fmt::Display + Hash + Eq + Sync + Send,
-{
+/// Runs simulations on `workers` threads, each with its own copy of the
+/// stats.  Workers send their results to a merger thread every
+/// `min_flush_interval` ms, which sends the merged stats back out every
+/// `merger_batch_size` updates.  Simulations under way on any worker count
+/// as virtual losses, so that workers don't all follow the same path.  The
+/// merger keeps its stats within the memory limit, and workers' copies are
+/// cut down with them whenever the merged stats go back out.
+///
+/// What each worker sees then depends on when the others flush, so with a
+/// seed the workers run in rounds instead: each runs its share of a batch on
+/// its own random number stream from `params.seed`, and then adds in what
+/// the others found, with no virtual losses between them.
+pub struct Workers<G: Game + Hash + Eq + 'static, P = Uniform> {
+    params: MCTSParams,
+    workers: Mutex<Vec<mpsc::Sender<WorkerMessage<G>>>>,
+    merger: Mutex<mpsc::SyncSender<MergerMessage<G>>>,
+    remaining: Arc<AtomicUsize>,
+    // The merged stats as of the end of the last search.
+    stats: Mutex<HashMap<G, Entry>>,
+    threads: Mutex<Vec<thread::JoinHandle<()>>>,
+    merger_thread: Mutex<Option<thread::JoinHandle<()>>>,
+    rollout: PhantomData<P>,
 }
 
-impl<G: Sync> Strategy<G> for MCTS<G>
-where
-    G: RandGame + fmt::Display + Hash + Eq,
-{
-    type Params = MCTSParams;
-
-    fn decide(&mut self, game: &G) -> G::Move {
-        for tx in &self.workers {
-            tx.send(WorkerMessage::UpdateCur(Some(game.clone())))
-                .expect("UpdateCur failed.");
+impl<G: Game + Hash + Eq + 'static, P> Workers<G, P> {
+    fn stop(&self) {
+        for tx in self.workers.lock().unwrap().iter() {
+            let _ = tx.send(WorkerMessage::Stop);
         }
-        self.merger
-            .send(MergerMessage::Prune(game.clone()))
-            .expect("Prune failed.");
+        for handle in self.threads.lock().unwrap().drain(..) {
+            handle.join().expect("MCTS worker panicked.");
+        }
+        // Only once no worker can still be flushing to it.
+        if let Some(handle) = self.merger_thread.lock().unwrap().take() {
+            let _ = self.merger.lock().unwrap().send(MergerMessage::Stop);
+            handle.join().expect("MCTS merger panicked.");
+        }
+    }
+}
 
-        thread::sleep(Duration::from_millis(self.params.timeout));
+impl<G: Game + Hash + Eq + 'static, P> Workers<G, P> {
+    // Takes the merged stats from the merger, once it has everything sent
+    // to it so far.
+    fn fetch_stats(&self) {
         let (tx, rx) = mpsc::channel();
-        self.merger.send(MergerMessage::GetStats(tx)).expect(
-            "GetStats request didn't send",
-        );
-        let stats = rx.recv().expect("Couldn't get stats.");
-
-        let nexts = game.possible_moves().into_iter().map(|m| {
-            let vm = *m.valid_move();
-            (vm, m.apply())
-        });
-
-        // let nexts2 = game.possible_moves().into_iter().map(|m| {
-        //     let vm = *m.valid_move();
-        //     (vm, m.apply())
-        // });
-
-        // for (_, g) in nexts2 {
-        //     let s = stats.get(&g).unwrap();
-        //     println!("{:?}\n{}", s, g);
-        // }
-
-        let (best, stats) = nexts
-            .into_iter()
-            .flat_map(|(m, g)| stats.get(&g).map(|s| (m, s)))
-            .max_by(|t1, t2| {
-                let s1 = t1.1;
-                let s2 = t2.1;
-                s1.visits.cmp(&s2.visits)
-                // (s1.losses as f64 / s1.visits as f64)
-                //     .partial_cmp(&(s2.losses as f64 / s2.visits as f64))
-                //     .unwrap_or(Less)
-            })
-            .expect("3");
-
-        println!("Best: {:?}", stats);
-        best
+        self.merger
+            .lock()
+            .unwrap()
+            .send(MergerMessage::GetStats(tx))
+            .expect("GetStats request didn't send");
+        *self.stats.lock().unwrap() = rx.recv().expect("Couldn't get stats.");
     }
 
-    fn create(params: MCTSParams) -> Self {
+    // Runs each batch as one round across the workers, with every worker's
+    // share and what it sees fixed in advance.
+    fn search_in_rounds(&self, root: &G, mut budget: Budget) {
+        let workers = self.workers.lock().unwrap();
+        let n = workers.len() as u64;
+        while let Some(sims) = budget.next_batch() {
+            let (tx, rx) = mpsc::channel();
+            for (i, w) in workers.iter().enumerate() {
+                let share = sims / n + if (i as u64) < sims % n { 1 } else { 0 };
+                w.send(WorkerMessage::Round(root.clone(), share, tx.clone()))
+                    .expect("Round failed.");
+            }
+            let mut rounds: Vec<_> = (0..n).map(|_| HashMap::new()).collect();
+            for _ in 0..n {
+                let (i, updates) = rx.recv().expect("Worker didn't finish its round.");
+                rounds[i] = updates;
+            }
 
+            let merge = |skip: Option<usize>| {
+                let mut merged = HashMap::new();
+                for (_, updates) in rounds.iter().enumerate().filter(|&(i, _)| Some(i) != skip) {
+                    for (g, &s) in updates {
+                        *merged.entry(g.clone()).or_insert(Entry::ZERO) += s;
+                    }
+                }
+                merged
+            };
+            for (i, w) in workers.iter().enumerate() {
+                w.send(WorkerMessage::Share(root.clone(), merge(Some(i))))
+                    .expect("Share failed.");
+            }
+            self.merger
+                .lock()
+                .unwrap()
+                .send(MergerMessage::Merge(merge(None)))
+                .expect("Merge failed.");
+        }
+        self.fetch_stats();
+    }
+}
+
+impl<G: Game + Hash + Eq + 'static, P> StatsTable<G> for Workers<G, P> {
+    fn lookup(&self, g: &G) -> Option<Entry> {
+        self.stats.lock().unwrap().lookup(g)
+    }
+}
+
+impl<G, P> Backend<G> for Workers<G, P>
+where
+    G: RandGame + Hash + Eq + 'static,
+    P: RolloutPolicy<G> + Clone + Send + 'static,
+{
+    type Rollout = P;
+
+    fn create(params: MCTSParams, rollout: P) -> Self {
         let mut workers = Vec::new();
+        let mut threads = Vec::new();
+        let remaining = Arc::new(AtomicUsize::new(0));
+        let in_flight = Arc::new(InFlight::default());
 
         let (merger_tx, merger_rx) = mpsc::sync_channel(params.merger_queue_bound);
-        for _ in 0..params.workers {
+        for id in 0..params.workers as usize {
             let (tx, rx) = mpsc::channel();
-            let worker = MCTSWorker {
-                input: rx,
-                last_flush: Instant::now(),
-                merger: merger_tx.clone(),
-                cur: None,
-                stats_cache: HashMap::new(),
-                updates: HashMap::new(),
-                params: params.clone(),
+            // Seeded workers don't see each other's simulations under way.
+            let in_flight = if params.seed.is_some() {
+                Arc::new(InFlight::default())
+            } else {
+                in_flight.clone()
             };
+            let worker = MCTSWorker::new(
+                id,
+                rx,
+                merger_tx.clone(),
+                params,
+                rollout.clone(),
+                remaining.clone(),
+                in_flight,
+            );
             workers.push(tx);
-            worker.start();
+            threads.push(worker.start());
         }
 
-        let (priority_merger_tx, priority_merger_rx) = mpsc::channel();
-        let merger = MCTSMerger::new(
-            params.clone(),
-            workers.clone(),
-            merger_rx,
-            priority_merger_rx,
-        );
-        merger.start();
-        let new = MCTS {
+        let merger = MCTSMerger {
+            input: merger_rx,
+            worker_outputs: workers.clone(),
             params: params,
-            workers: workers,
-            merger: priority_merger_tx,
+            stats: HashMap::new(),
+            root: None,
+            merges: 0,
         };
+        let merger_thread = merger.start();
+
+        Workers {
+            params: params,
+            workers: Mutex::new(workers),
+            merger: Mutex::new(merger_tx),
+            remaining: remaining,
+            stats: Mutex::new(HashMap::new()),
+            threads: Mutex::new(threads),
+            merger_thread: Mutex::new(Some(merger_thread)),
+            rollout: PhantomData,
+        }
+    }
+
+    fn search(&self, root: &G, budget: Budget) {
+        if self.params.seed.is_some() {
+            return self.search_in_rounds(root, budget);
+        }
+        let sims = budget.max_sims().map(|n| n as usize).unwrap_or(::std::usize::MAX);
+        self.remaining.store(sims, Ordering::SeqCst);
+
+        let workers = self.workers.lock().unwrap();
+        for tx in workers.iter() {
+            tx.send(WorkerMessage::UpdateCur(Some(root.clone())))
+                .expect("UpdateCur failed.");
+        }
+
+        loop {
+            if budget.out_of_time() || budget.stopped() || self.remaining.load(Ordering::SeqCst) == 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // Once every worker has handed in its updates, the merger has them
+        // queued before our request.
+        let (tx, rx) = mpsc::channel();
+        for w in workers.iter() {
+            w.send(WorkerMessage::Pause(tx.clone())).expect("Pause failed.");
+        }
+        for _ in workers.iter() {
+            rx.recv().expect("Worker didn't pause.");
+        }
+
+        self.fetch_stats();
+    }
+
+    fn prune(&self, root: &G) {
+        self.merger
+            .lock()
+            .unwrap()
+            .send(MergerMessage::Prune(root.clone()))
+            .expect("Prune failed.");
+        // Each worker cuts down its own copy, rather than being sent all of
+        // what's left.
+        for tx in self.workers.lock().unwrap().iter() {
+            let _ = tx.send(WorkerMessage::Prune(root.clone()));
+        }
+        self.stats.lock().unwrap().retain(|k, _| root.reachable(k));
+    }
+
+    /// The size of the merged stats as of the end of the last search.
+    fn size(&self) -> Size {
+        Size::of_table::<G>(self.stats.lock().unwrap().len())
+    }
+
+    fn shutdown(&self) {
+        self.stop();
+    }
+}
+
+impl<G: Game + Hash + Eq + 'static, P> Drop for Workers<G, P> {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::Strategy;
+    use game::connectfour::{Color, ConnectFour};
+    use strategies::mcts_core::test::{assert_stops_at_sim_limit, parallel_params};
+
+    fn params() -> MCTSParams {
+        parallel_params(Some(50), None)
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let game = ConnectFour::new(&Color::R);
+        let mut strategy = MCTS::<ConnectFour>::create(params());
+        let m = strategy.decide(&game);
+        assert!(game.move_valid(&m));
+
+        // Joins both workers and the merger.
+        strategy.shutdown();
+        assert!(strategy.backend().threads.lock().unwrap().is_empty());
+        assert!(strategy.backend().merger_thread.lock().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_seed_reproduces_search() {
+        let game = ConnectFour::new(&Color::R);
+        let analyze = || {
+            let mut strategy = MCTS::<ConnectFour>::create(MCTSParams {
+                seed: Some([1, 2, 3, 4]),
+                ..parallel_params(None, Some(500))
+            });
+            let analysis = strategy.analyze(&game);
+            assert_eq!(strategy.backend().stats(&game).map(|s| s.visits), Some(500));
+            analysis.moves
+        };
+        assert_eq!(analyze(), analyze());
+    }
+
+    #[test]
+    fn test_workers_prune_their_own_stats() {
+        let (_tx, rx) = mpsc::channel();
+        let (merger, _merger_rx) = mpsc::sync_channel(1);
+        let mut worker = MCTSWorker::new(
+            0,
+            rx,
+            merger,
+            params(),
+            Uniform,
+            Arc::new(AtomicUsize::new(0)),
+            Arc::new(InFlight::default()),
+        );
+        let game = ConnectFour::new(&Color::R);
+        let (mut left, mut right) = (game.clone(), game.clone());
+        left.try_move((0, Color::R));
+        right.try_move((6, Color::R));
+        for g in &[&game, &left, &right] {
+            worker.stats_cache.insert((*g).clone(), Entry::ZERO);
+            worker.updates.insert((*g).clone(), Entry::ZERO);
+        }
+
+        assert!(worker.handle(WorkerMessage::Prune(left.clone())));
+        assert_eq!(worker.stats_cache.keys().collect::<Vec<_>>(), vec![&left]);
+        assert_eq!(worker.updates.keys().collect::<Vec<_>>(), vec![&left]);
+    }
 
-        new
+    #[test]
+    fn test_stops_at_sim_limit() {
+        assert_stops_at_sim_limit::<Workers<ConnectFour>>(300);
     }
 }
@@ -1,236 +1,165 @@
-#![allow(unused)]
-use super::*;
+use super::mcts_core::{self, Backend, Budget, Entry, InFlight, MemoryLimit, Scratch, Size, StatsTable};
+use super::rollout::{RolloutPolicy, Uniform};
 use std::ops::{Deref, DerefMut};
-use futures::prelude::*;
-use futures::future;
-use futures_timer::Delay;
 use std::hash::Hash;
 use game::*;
-use std::mem::size_of;
-use rand::XorShiftRng;
-use rand;
-use std::fmt;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::HashMap;
 use std::sync::*;
-use std::thread;
-use std::time::{Instant, Duration};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use rayon;
 use rayon::prelude::*;
-use rayon_futures::{ScopeFutureExt, RayonFuture};
-use std::collections::LinkedList;
-use std::ops::Add;
 use evmap;
 
-impl Add for Stats {
-    type Output = Stats;
-    fn add(self, rhs: Self) -> Self::Output {
-        Stats {
-            wins: self.wins + rhs.wins,
-            losses: self.losses + rhs.losses,
-            visits: self.visits + rhs.visits,
-        }
+pub use super::mcts_core::MCTSParams;
+
+/// MCTS run in batches on a rayon pool, each simulation playing out new
+/// positions with its own copy of a `P`.
+pub type MCTS<G, P = Uniform> = mcts_core::MCTS<G, Rayon<G, P>>;
+
+impl evmap::ShallowCopy for Entry {
+    unsafe fn shallow_copy(&mut self) -> Self {
+        *self
     }
 }
 
-use std::iter;
-impl<'a> iter::Sum<&'a Stats> for Stats {
-    fn sum<I>(iter: I) -> Self
-    where
-        I: Iterator<Item = &'a Self>,
-    {
-        iter.fold(Stats::ZERO, |a, b| a + *b)
+impl<G: Eq + Hash + Clone> StatsTable<G> for evmap::ReadHandle<G, Entry> {
+    fn lookup(&self, g: &G) -> Option<Entry> {
+        self.get_and(g, |vs| vs[0])
     }
 }
 
-/// Perform a generic `par_extend` by collecting to a `LinkedList<Vec<_>>` in
-/// parallel, then extending the collection sequentially.
-fn extend<G, I>(mut collection: &mut StatsWriter<G>, par_iter: I)
-where
-    I: IntoParallelIterator<Item = (G, Stats)>,
-    G: Send + Eq + Hash + Clone
-{
-    let list = par_iter
-        .into_par_iter()
-        .fold(Vec::new, |mut vec, elem| {
-            vec.push(elem);
-            vec
-        })
-        .map(|vec| {
-            let mut list = LinkedList::new();
-            list.push_back(vec);
-            list
-        })
-        .reduce(LinkedList::new, |mut list1, mut list2| {
-            list1.append(&mut list2);
-            list1
-        });
-
-    for vec in list {
-        // println!("vec size: {}", vec.len());
-        collection.extend(vec);
-    }
+/// One simulation's results, and the path it counts as under way along
+/// until they're written in.
+struct Simulation<G> {
+    path: Vec<G>,
+    updates: Vec<(G, Entry)>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Copy)]
-struct Stats {
-    wins: usize,
-    losses: usize,
-    visits: usize,
+/// The stats as of the start of a batch, as one simulation in it sees them.
+/// Its results only show up once the batch is written in, so the positions
+/// it walks through stay in flight until then rather than as it finishes.
+struct BatchTable<'a, G: 'a> {
+    stats: &'a evmap::ReadHandle<G, Entry>,
+    in_flight: &'a InFlight<G>,
+    path: RefCell<Vec<G>>,
+    // Whether the rest of the batch sees the path as under way as soon as
+    // it's entered, or only once the caller adds it.
+    shared: bool,
 }
 
-impl Stats {
-    const WIN: Stats = Stats {
-        wins: 1,
-        losses: 0,
-        visits: 1,
-    };
-    const LOSS: Stats = Stats {
-        wins: 0,
-        losses: 1,
-        visits: 1,
-    };
-    const TIE: Stats = Stats {
-        wins: 0,
-        losses: 0,
-        visits: 1,
-    };
-    const ZERO: Stats = Stats {
-        wins: 0,
-        losses: 0,
-        visits: 0,
-    };
-
-    fn delta(maybe_won: Option<bool>) -> Self {
-        match maybe_won {
-            None => Self::TIE,
-            Some(won) => if won { Self::WIN } else { Self::LOSS },
+impl<'a, G: RandGame + Eq + Hash + Clone> BatchTable<'a, G> {
+    /// Runs simulation number `index` of the search, which picks its random
+    /// number stream.
+    fn simulate<P>(self, params: &MCTSParams, rollout: &P, root: &G, index: usize) -> Simulation<G>
+    where
+        P: RolloutPolicy<G> + Clone,
+    {
+        let mut rng = seeded_rng(params.seed, index as u64);
+        let mut updates = Vec::new();
+        mcts_core::simulate(
+            params,
+            &self,
+            &mut rollout.clone(),
+            &mut rng,
+            root,
+            &mut Scratch::default(),
+            &mut updates,
+        );
+        Simulation {
+            path: self.path.into_inner(),
+            updates: updates,
         }
     }
 }
 
-impl evmap::ShallowCopy for Stats {
-    unsafe fn shallow_copy(&mut self) -> Self {
-        *self
+impl<'a, G: Eq + Hash + Clone> StatsTable<G> for BatchTable<'a, G> {
+    fn lookup(&self, g: &G) -> Option<Entry> {
+        self.stats.lookup(g)
     }
-}
 
-#[derive(Clone)]
-struct MCTSState<G: Send + Eq + Hash + Clone> {
-    stats: evmap::ReadHandle<G, Stats>,
-    current: Arc<Mutex<Option<G>>>,
-}
-
-
-impl<G: Game + Send + Eq + Hash + Clone> MCTSState<G> {
-    fn stats(&self, game: &G) -> Option<Stats> {
-        self.stats.get_and(game, |vs| vs[0])
+    fn in_flight(&self, g: &G) -> usize {
+        self.in_flight.count(g)
     }
-    fn simulate<R: rand::Rng>(
-        &self,
-        params: &MCTSParams,
-        rng: &mut R,
-        game: &G,
-        stats_output: &mut Vec<(G, Stats)>,
-    ) -> Option<G::Agent> {
-        let acting = game.to_act();
-        let nexts = game.possible_moves();
-        let winner = if nexts.is_empty() {
-            game.winner()
-        } else {
-            let parent_visits = {
-                self.stats(&game).map(|s| s.visits).unwrap_or(1)
-            };
-            let g = self.select(params, rng, nexts, parent_visits, acting);
-            self.simulate(params, rng, &g, stats_output)
-        };
-
-        let stats_delta: Stats = Stats::delta(winner.map(|w| w == acting));
-        stats_output.push((game.clone(), stats_delta));
 
-        winner
-    }
-
-    fn select<R: rand::Rng>(
-        &self,
-        params: &MCTSParams,
-        rng: &mut R,
-        choices: Vec<ValidMove<G>>,
-        parent_visits: usize,
-        acting: G::Agent,
-    ) -> G {
-        let n = choices.len();
-
-        if choices.is_empty() {
-            panic!("Shouldn't have gotten here.")
-        }
-        let i: usize = rng.gen();
-        let random_choice = choices[i % n].clone().apply();
-        let games: Vec<_> = choices
-            .into_iter()
-            .flat_map(|m| {
-                let game = m.apply();
-                let stats = self.stats(&game);
-                stats.map(|s| (game, s))
-            })
-            .collect();
-
-        if games.len() == n {
-            let x = games.into_iter().max_by(|t1, t2| {
-                let t1 = (&t1.0, t1.1);
-                let t2 = (&t2.0, t2.1);
-                params
-                    .key(t1, parent_visits as f64, acting)
-                    .partial_cmp(&params.key(t2, parent_visits as f64, acting))
-                    .expect("2")
-            });
-            x.expect("1").0
-        } else {
-            random_choice
+    fn enter(&self, g: &G) {
+        if self.shared {
+            self.in_flight.add(g);
         }
+        self.path.borrow_mut().push(g.clone());
     }
 }
 
 struct StatsWriter<G: Send + Eq + Hash + Clone> {
-    stats: evmap::WriteHandle<G, Stats>,
+    stats: evmap::WriteHandle<G, Entry>,
+    // Totals written since the last refresh, which readers can't see yet.
+    pending: HashMap<G, Entry>,
 }
 
 impl<G: Send + Eq + Hash + Clone> StatsWriter<G> {
-    fn compact(&mut self) {
-        let read_handle: evmap::ReadHandle<G, Stats> = self.clone();
-        read_handle.for_each(|k, vs: &[Stats]| if vs.len() > 1 {
-            self.update(k.clone(), vs.iter().sum());
-        });
-        self.refresh();
-        println!("Compaction complete.");
+    /// Adds a simulation's results, to show up at the next `publish`, and
+    /// stops counting its path as under way.
+    fn apply(&mut self, sim: Simulation<G>, in_flight: &InFlight<G>) {
+        for (g, s) in sim.updates {
+            let stats = &self.stats;
+            let stat = self.pending
+                .entry(g.clone())
+                .or_insert_with(|| stats.get_and(&g, |vs| vs[0]).unwrap_or(Entry::ZERO));
+            *stat += s;
+            self.stats.update(g, *stat);
+        }
+        for g in &sim.path {
+            in_flight.remove(g);
+        }
     }
-}
 
-impl<G: Send + Eq + Hash + Clone> ParallelExtend<(G, Stats)> for StatsWriter<G> {
-    fn par_extend<I: IntoParallelIterator<Item = (G, Stats)>>(&mut self, par_iter: I) {
-        extend(self, par_iter);
+    fn publish(&mut self) {
+        self.pending.clear();
+        self.stats.refresh();
     }
-}
 
-impl<G: Send + Eq + Hash + Clone> iter::Extend<(G, Stats)> for StatsWriter<G> {
-    fn extend<T>(&mut self, iter: T)
+    /// Drops every position that can't come up from `root` any more.
+    fn prune(&mut self, root: &G)
     where
-        T: IntoIterator<Item = (G, Stats)>,
+        G: Game,
     {
-        use std::collections::HashMap;
-        let mut local_map: HashMap<G, Stats> = HashMap::new();
-        iter.into_iter().for_each(|(g, s)| {
-            let stat = local_map.entry(g.clone())
-                .or_insert_with(|| self.stats.get_and(&g, |vs| vs[0]).unwrap_or(Stats::ZERO));
-            *stat = *stat + s;
-            self.stats.update(g, *stat);
+        let read_handle: evmap::ReadHandle<G, Entry> = self.clone();
+        let mut stale = Vec::new();
+        read_handle.for_each(|k, _| if !root.reachable(k) {
+            stale.push(k.clone());
         });
-        // println!("Refreshing.");
-        self.stats.refresh();
-        // println!("Refreshed.");
+        for k in stale {
+            self.empty(k);
+        }
+        self.refresh();
+    }
+
+    /// Evicts positions under `root` until there are `target` left, as far
+    /// as `mcts_core::evictions` allows.
+    fn evict(&mut self, root: &G, target: usize)
+    where
+        G: Game,
+    {
+        let read_handle: evmap::ReadHandle<G, Entry> = self.clone();
+        for k in mcts_core::evictions(&read_handle, root, read_handle.len(), target) {
+            self.empty(k);
+        }
+        self.refresh();
+    }
+
+    #[allow(dead_code)]
+    fn compact(&mut self) {
+        let read_handle: evmap::ReadHandle<G, Entry> = self.clone();
+        read_handle.for_each(|k, vs: &[Entry]| if vs.len() > 1 {
+            self.update(k.clone(), vs.iter().sum());
+        });
+        self.refresh();
     }
 }
 
 impl<G: Send + Eq + Hash + Clone> Deref for StatsWriter<G> {
-    type Target = evmap::WriteHandle<G, Stats>;
+    type Target = evmap::WriteHandle<G, Entry>;
     fn deref(&self) -> &Self::Target {
         &self.stats
     }
@@ -242,189 +171,215 @@ impl<G: Send + Eq + Hash + Clone> DerefMut for StatsWriter<G> {
     }
 }
 
-
-
-#[derive(Clone, Copy)]
-pub struct MCTSParams {
-    // Time limit in ms.
-    pub timeout: u64,
-    pub c: f64,
-    pub workers: u64,
-    pub worker_batch_size: u64,
-    pub merger_queue_bound: usize,
-    pub merger_batch_size: u64,
-    pub min_flush_interval: u64,
+/// Runs each batch of simulations in parallel on a rayon pool of `workers`
+/// threads (rayon's default if 0).  Every simulation in a batch reads the
+/// stats as of the start of the batch, and their results are written in
+/// once the batch is done.  Until its own results are written in, every
+/// simulation in the batch counts as a virtual loss along its path, to keep
+/// the rest of the batch from following it.  Positions are evicted between
+/// batches to keep within the memory limit.
+///
+/// With a seed the simulations of a batch run in rounds of one per thread,
+/// so that which of them count as under way doesn't depend on how the
+/// threads are scheduled, and the search plays the same way again with the
+/// same number of `workers`.
+pub struct Rayon<G: Send + Eq + Hash + Clone, P = Uniform> {
+    params: MCTSParams,
+    rollout: P,
+    pool: rayon::ThreadPool,
+    in_flight: InFlight<G>,
+    // Simulations started so far, each of which takes the next random
+    // number stream from `params.seed`.
+    sims: AtomicUsize,
+    stats: Mutex<evmap::ReadHandle<G, Entry>>,
+    writer: Mutex<StatsWriter<G>>,
 }
 
-impl MCTSParams {
-    fn key<G: Game>(&self, (g, s): (&G, Stats), n: f64, acting: G::Agent) -> f64 {
-        let wins = if acting == g.to_act() {
-            s.wins
-        } else {
-            s.losses
-        } as f64;
-        let visits = s.visits as f64;
-        wins / visits + self.c * (n.ln() / visits).sqrt()
+impl<G, P> Rayon<G, P>
+where
+    G: RandGame + Hash + Eq + Sync + Send,
+    P: RolloutPolicy<G> + Clone + Sync,
+{
+    /// Runs `n` simulations at once from number `first` on, against the
+    /// stats as of now.  `shared` as for `BatchTable`.
+    fn run(&self, root: &G, first: usize, n: usize, shared: bool) -> Vec<Simulation<G>> {
+        let params = &self.params;
+        let in_flight = &self.in_flight;
+        let rollout = &self.rollout;
+        let read_handle = self.stats.lock().expect("Lock poisoned").clone();
+        self.pool.install(|| {
+            rayon::iter::repeatn(read_handle, n)
+                .enumerate()
+                .with_min_len(cmp::max(n / 8, 1))
+                .map(|(i, read_handle)| {
+                    let table = BatchTable {
+                        stats: &read_handle,
+                        in_flight: in_flight,
+                        path: RefCell::new(Vec::new()),
+                        shared: shared,
+                    };
+                    table.simulate(params, rollout, root, first + i)
+                })
+                .collect()
+        })
     }
-}
 
-pub struct MCTS<G: Clone + Sync + Hash + Eq + RandGame> {
-    params: MCTSParams,
-    state: MCTSState<G>,
-    notify: Condvar,
-    stats_writer: StatsWriter<G>,
-}
-
-struct WrapGen<T>(T);
-impl<G: Generator<Return = ()>> Iterator for WrapGen<G> {
-    type Item = G::Yield;
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.0.resume() {
-            GeneratorState::Complete(_) => None,
-            GeneratorState::Yielded(x) => Some(x),
+    /// Runs `n` simulations from number `first` on in rounds of one per
+    /// thread.  Each round sees the paths of the rounds before it as under
+    /// way, but none from its own.
+    fn run_rounds(&self, root: &G, first: usize, n: usize) -> Vec<Simulation<G>> {
+        let round = self.pool.current_num_threads();
+        let mut sims = Vec::with_capacity(n);
+        while sims.len() < n {
+            let done = sims.len();
+            let batch = self.run(root, first + done, cmp::min(round, n - done), false);
+            for sim in &batch {
+                for g in &sim.path {
+                    self.in_flight.add(g);
+                }
+            }
+            sims.extend(batch);
         }
+        sims
     }
 }
 
-use std::ops::{Generator, GeneratorState};
-impl<G> MCTS<G>
-where
-    G: RandGame + fmt::Display + Hash + Eq + Sync + Send,
-{
+impl<G: Send + Eq + Hash + Clone, P> StatsTable<G> for Rayon<G, P> {
+    fn lookup(&self, g: &G) -> Option<Entry> {
+        self.stats.lock().expect("Lock poisoned").lookup(g)
+    }
 }
 
-impl<G: Sync> Strategy<G> for MCTS<G>
+impl<G, P> Backend<G> for Rayon<G, P>
 where
-    G: RandGame
-        + fmt::Display
-        + Hash
-        + Eq
-        + Clone
-        + fmt::Debug
-        + 'static,
+    G: RandGame + Hash + Eq + Sync + Send,
+    P: RolloutPolicy<G> + Clone + Sync,
 {
-    type Params = MCTSParams;
-
-    fn decide(&mut self, game: &G) -> G::Move {
-        println!("Deciding for {}..", game);
-        {
-            println!("Need lock");
-            *(self.state.current.lock().expect("Lock poisoned")) = Some(game.clone());
-            println!("Got lock lock");
-        }
+    type Rollout = P;
 
+    fn create(params: MCTSParams, rollout: P) -> Self {
+        let (read_handle, write_handle) = evmap::new::<G, Entry>();
         let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(params.workers as usize)
             .breadth_first()
             .build()
             .unwrap();
 
-
-        let duration = Duration::from_millis(self.params.timeout);
-        let start_time = Instant::now();
-
-        let sims = 120000;
-        let mut sims_run = 0;
-        let sims_needed = 240000;
-        while sims_run < sims_needed {
-            self.schedule_work(&pool, sims);
-            sims_run += sims;
+        Rayon {
+            params: params,
+            rollout: rollout,
+            pool: pool,
+            in_flight: InFlight::default(),
+            sims: AtomicUsize::new(0),
+            stats: Mutex::new(read_handle),
+            writer: Mutex::new(StatsWriter {
+                stats: write_handle,
+                pending: HashMap::new(),
+            }),
         }
+    }
 
-        println!(
-            "Finished {:?} sims.  {:?} has elapsed.",  
-            sims_run,
-            start_time.elapsed(),
-        );
-
-        let nexts = game.possible_moves().into_iter().map(|m| {
-            let vm = *m.valid_move();
-            (vm, m.apply())
-        });
-
-        // let nexts2 = game.possible_moves().into_iter().map(|m| {
-        //     let vm = *m.valid_move();
-        //     (vm, m.apply())
-        // });
-
-        // for (_, g) in nexts2 {
-        //     let s = stats.get(&g).unwrap();
-        //     println!("{:?}\n{}", s, g);
-        // }
-
-        let (best, stats) = nexts
-            .into_iter()
-            .flat_map(|(m, g)| self.state.stats(&g).map(|s| (m, s)))
-            .max_by(|t1, t2| {
-                let s1 = t1.1;
-                let s2 = t2.1;
-                s1.visits.cmp(&s2.visits)
-                // (s1.losses as f64 / s1.visits as f64)
-                //     .partial_cmp(&(s2.losses as f64 / s2.visits as f64))
-                //     .unwrap_or(Less)
-            })
-            .expect("3");
-
-        println!("Best: {:?}", stats);
-        best
+    fn search(&self, root: &G, mut budget: Budget) {
+        while let Some(n) = budget.next_batch() {
+            let n = n as usize;
+            let first = self.sims.fetch_add(n, Ordering::SeqCst);
+            let sims = if self.params.seed.is_some() {
+                self.run_rounds(root, first, n)
+            } else {
+                self.run(root, first, n, true)
+            };
+            let mut writer = self.writer.lock().expect("Lock poisoned");
+            for sim in sims {
+                writer.apply(sim, &self.in_flight);
+            }
+            writer.publish();
+            let size = self.size();
+            if let Some(target) = MemoryLimit::target(self.params.memory_limit, size) {
+                writer.evict(root, target);
+            }
+        }
     }
 
+    fn prune(&self, root: &G) {
+        self.writer.lock().expect("Lock poisoned").prune(root);
+    }
 
-    fn create(params: MCTSParams) -> Self {
-        let seed = rand::random::<[u32; 4]>();
-        let mut rng: XorShiftRng = rand::SeedableRng::from_seed(seed);
+    fn size(&self) -> Size {
+        Size::of_table::<G>(self.stats.lock().expect("Lock poisoned").len())
+    }
+}
 
-        let (read_handle, mut write_handle) = evmap::new::<G, Stats>();
-        let state = MCTSState {
-            stats: read_handle,
-            current: Arc::new(Mutex::new(None)),
-        };
-        let mut stats_writer = StatsWriter { stats: write_handle };
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::Strategy;
+    use game::connectfour::{Color, ConnectFour};
+    use strategies::mcts_core::test::{assert_stops_at_sim_limit, parallel_params, sim_params};
 
-        let seed = rand::random::<[u32; 4]>();
-        let mut rng: XorShiftRng = rand::SeedableRng::from_seed(seed);
+    #[test]
+    fn test_stops_at_sim_limit() {
+        assert_stops_at_sim_limit::<Rayon<ConnectFour>>(250);
+    }
 
-        let mcts = MCTS {
-            params: params,
-            state: state,
-            notify: Condvar::new(),
-            stats_writer: stats_writer,
-        };
+    #[test]
+    fn test_releases_each_path_as_written_in() {
+        let game = ConnectFour::new(&Color::R);
+        let backend = Rayon::<ConnectFour>::create(parallel_params(None, Some(100)), Uniform);
+        backend.search(&game, Budget::new(&backend.params));
+
+        // Two simulations of a batch, under way until written in.
+        let read_handle = backend.stats.lock().unwrap().clone();
+        let sims: Vec<_> = (0..2)
+            .map(|i| {
+                let table = BatchTable {
+                    stats: &read_handle,
+                    in_flight: &backend.in_flight,
+                    path: RefCell::new(Vec::new()),
+                    shared: true,
+                };
+                table.simulate(&backend.params, &Uniform, &game, i)
+            })
+            .collect();
+        let paths: Vec<Vec<ConnectFour>> = sims.iter().map(|s| s.path.clone()).collect();
+        assert!(paths.iter().all(|p| p.len() > 1));
+        assert_eq!(backend.in_flight.count(&game), 2);
+
+        // Writing in the first only takes its own path out of flight.
+        let mut writer = backend.writer.lock().unwrap();
+        let mut sims = sims.into_iter();
+        writer.apply(sims.next().unwrap(), &backend.in_flight);
+        for g in paths[0].iter().chain(&paths[1]) {
+            let left = paths[1].iter().filter(|&h| h == g).count();
+            assert_eq!(backend.in_flight.count(g), left);
+        }
+        writer.apply(sims.next().unwrap(), &backend.in_flight);
+        assert!(paths[1].iter().all(|g| backend.in_flight.count(g) == 0));
 
-        mcts
+        writer.publish();
+        assert_eq!(backend.stats(&game).map(|s| s.visits), Some(102));
     }
-}
 
-
-impl<G> MCTS<G>
-where
-    G: Clone + Sync + Hash + Eq + RandGame + fmt::Debug + 'static,
-{
-    fn schedule_work(&mut self, pool: &rayon::ThreadPool, i: usize) {
-        let state = self.state.clone();
-        let params = self.params.clone();
-        pool.install(|| self.schedule(&pool, state, &params, i));
-        println!("Completed {} simulations.", i);
+    #[test]
+    fn test_seed_reproduces_search() {
+        let game = ConnectFour::new(&Color::R);
+        let analyze = || {
+            let mut strategy = MCTS::<ConnectFour>::create(MCTSParams {
+                workers: 2,
+                seed: Some([1, 2, 3, 4]),
+                ..sim_params(500)
+            });
+            strategy.analyze(&game).moves
+        };
+        assert_eq!(analyze(), analyze());
     }
 
-    fn schedule(
-        &mut self,
-        pool: &rayon::ThreadPool,
-        state: MCTSState<G>,
-        params: &MCTSParams,
-        i: usize,
-    ) {
-        let current = state.current.lock().expect("Lock poisoned").clone();
-        (&mut self.stats_writer).par_extend(
-            rayon::iter::repeatn(current.map(|g| (g, state.clone())), i)
-                .with_min_len(i / 8)
-                .filter_map(|x| x)
-                .flat_map(|(game, state)| {
-                    let seed = rand::random::<[u32; 4]>();
-                    let mut rng: XorShiftRng = rand::SeedableRng::from_seed(seed);
-                    let mut stats_output = Vec::new();
-                    state.simulate(&params, &mut rng, &game, &mut stats_output);
-                    stats_output.into_par_iter()
-                }).collect::<Vec<_>>().into_par_iter().with_min_len(10000)
-        );
+    #[test]
+    fn test_stops_at_timeout() {
+        let game = ConnectFour::new(&Color::R);
+        let mut strategy = MCTS::<ConnectFour>::create(parallel_params(Some(0), None));
+        strategy.decide(&game);
+        // The deadline has passed by the time the first batch is in, so
+        // that's the only one.
+        assert_eq!(strategy.backend().stats(&game).map(|s| s.visits), Some(100));
     }
 }
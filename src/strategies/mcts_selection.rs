@@ -0,0 +1,250 @@
+use super::mcts_core::Stats;
+use rand::Rng;
+use rand::distributions::IndependentSample;
+use rand::distributions::gamma::Gamma;
+use std::str::FromStr;
+
+/// Decides which child the walk down the tree takes.  Stats handed to a
+/// policy are from the point of view of the player choosing the move.
+pub trait SelectionPolicy {
+    /// Value of a child with stats `s` under a parent visited
+    /// `parent_visits` times.  `prior` is the child's share of the parent's
+    /// move priors.  The walk takes the child with the highest value.
+    fn score<R: Rng>(&self, rng: &mut R, s: Stats, parent_visits: usize, prior: f64) -> f64;
+
+    /// Whether every child is tried once, in random order, before any are
+    /// scored.  Policies that don't are also asked to score children that
+    /// have no visits yet.
+    fn tries_all_first(&self) -> bool {
+        true
+    }
+
+    /// Whether `score` looks at `prior`, so it's worth working out.
+    fn uses_priors(&self) -> bool {
+        false
+    }
+}
+
+/// The mean result plus an exploration bonus that shrinks with visits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UCB1 {
+    pub c: f64,
+}
+
+impl SelectionPolicy for UCB1 {
+    fn score<R: Rng>(&self, _: &mut R, s: Stats, parent_visits: usize, _: f64) -> f64 {
+        let visits = s.visits as f64;
+        s.reward() / visits + self.c * ((parent_visits as f64).ln() / visits).sqrt()
+    }
+}
+
+/// UCB1 with the bonus scaled by an upper bound on the variance of each
+/// child's results, so children with steady results get explored less.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UCB1Tuned;
+
+impl SelectionPolicy for UCB1Tuned {
+    fn score<R: Rng>(&self, _: &mut R, s: Stats, parent_visits: usize, _: f64) -> f64 {
+        let visits = s.visits as f64;
+        let log_n = (parent_visits as f64).ln();
+        let mean = s.reward() / visits;
+        // Results are 1 for a win and 0.5 for a draw.
+        let mean_sq = (s.wins as f64 + 0.25 * s.draws as f64) / visits;
+        let variance = mean_sq - mean * mean + (2.0 * log_n / visits).sqrt();
+        mean + (log_n / visits * variance.min(0.25)).sqrt()
+    }
+}
+
+/// The mean result plus a bonus weighted by the move's prior, as in
+/// AlphaZero.  Children are scored before they're visited, so the priors
+/// decide which get tried first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PUCT {
+    pub c: f64,
+}
+
+impl SelectionPolicy for PUCT {
+    fn score<R: Rng>(&self, _: &mut R, s: Stats, parent_visits: usize, prior: f64) -> f64 {
+        let visits = s.visits as f64;
+        // Unvisited children count as even.
+        let mean = if s.visits == 0 {
+            0.5
+        } else {
+            s.reward() / visits
+        };
+        mean + self.c * prior * (parent_visits as f64).sqrt() / (1.0 + visits)
+    }
+
+    fn tries_all_first(&self) -> bool {
+        false
+    }
+
+    fn uses_priors(&self) -> bool {
+        true
+    }
+}
+
+/// Samples each child's win rate from its Beta posterior, starting from a
+/// uniform prior, and takes the highest sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Thompson;
+
+impl SelectionPolicy for Thompson {
+    fn score<R: Rng>(&self, rng: &mut R, s: Stats, _: usize, _: f64) -> f64 {
+        let reward = s.reward();
+        // A Beta(a, b) sample is x / (x + y) for x ~ Gamma(a), y ~ Gamma(b).
+        let x = Gamma::new(reward + 1.0, 1.0).ind_sample(rng);
+        let y = Gamma::new(s.visits as f64 - reward + 1.0, 1.0).ind_sample(rng);
+        x / (x + y)
+    }
+}
+
+/// The selection policies an MCTS search can be configured with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Policy {
+    UCB1(UCB1),
+    UCB1Tuned(UCB1Tuned),
+    PUCT(PUCT),
+    Thompson(Thompson),
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy::UCB1(UCB1 { c: (2.0 as f64).sqrt() })
+    }
+}
+
+impl SelectionPolicy for Policy {
+    fn score<R: Rng>(&self, rng: &mut R, s: Stats, parent_visits: usize, prior: f64) -> f64 {
+        match *self {
+            Policy::UCB1(ref p) => p.score(rng, s, parent_visits, prior),
+            Policy::UCB1Tuned(ref p) => p.score(rng, s, parent_visits, prior),
+            Policy::PUCT(ref p) => p.score(rng, s, parent_visits, prior),
+            Policy::Thompson(ref p) => p.score(rng, s, parent_visits, prior),
+        }
+    }
+
+    fn tries_all_first(&self) -> bool {
+        match *self {
+            Policy::UCB1(ref p) => p.tries_all_first(),
+            Policy::UCB1Tuned(ref p) => p.tries_all_first(),
+            Policy::PUCT(ref p) => p.tries_all_first(),
+            Policy::Thompson(ref p) => p.tries_all_first(),
+        }
+    }
+
+    fn uses_priors(&self) -> bool {
+        match *self {
+            Policy::UCB1(ref p) => p.uses_priors(),
+            Policy::UCB1Tuned(ref p) => p.uses_priors(),
+            Policy::PUCT(ref p) => p.uses_priors(),
+            Policy::Thompson(ref p) => p.uses_priors(),
+        }
+    }
+}
+
+/// Parses a policy by name, with the usual constants: `ucb1`, `ucb1-tuned`,
+/// `puct` or `thompson`.
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ucb1" => Ok(Policy::default()),
+            "ucb1-tuned" => Ok(Policy::UCB1Tuned(UCB1Tuned)),
+            "puct" => Ok(Policy::PUCT(PUCT { c: 1.5 })),
+            "thompson" => Ok(Policy::Thompson(Thompson)),
+            _ => Err(format!("Unknown selection policy: {}", s)),
+        }
+    }
+}
+
+/// How much weight RAVE gives a child's all-moves-as-first results against
+/// its own as both pile up.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rave {
+    /// β = sqrt(k / (3n + k)) for a child visited n times, so the AMAF
+    /// results count for half after k visits.
+    Equivalence(f64),
+    /// Silver's minimum-MSE schedule, β = m / (n + m + 4b²nm) for m AMAF
+    /// visits, where b is the expected bias of the AMAF results.
+    MinimumMSE(f64),
+}
+
+impl Rave {
+    /// Weight of the AMAF results, between 0 and 1.
+    pub fn weight(&self, visits: usize, amaf_visits: usize) -> f64 {
+        let (n, m) = (visits as f64, amaf_visits as f64);
+        match *self {
+            Rave::Equivalence(k) => (k / (3.0 * n + k)).sqrt(),
+            Rave::MinimumMSE(b) => m / (n + m + 4.0 * b * b * n * m),
+        }
+    }
+
+    /// What to add to a child's score to swap part of its mean result for
+    /// its AMAF mean.  Both stats are from the point of view of the player
+    /// choosing the move.
+    pub fn adjustment(&self, s: Stats, amaf: Stats) -> f64 {
+        if amaf.visits == 0 {
+            return 0.0;
+        }
+        let mean = if s.visits == 0 {
+            0.5
+        } else {
+            s.reward() / s.visits as f64
+        };
+        let amaf_mean = amaf.reward() / amaf.visits as f64;
+        self.weight(s.visits, amaf.visits) * (amaf_mean - mean)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{SeedableRng, XorShiftRng};
+
+    fn stats(wins: usize, losses: usize, draws: usize) -> Stats {
+        Stats {
+            wins: wins,
+            losses: losses,
+            draws: draws,
+            visits: wins + losses + draws,
+        }
+    }
+
+    #[test]
+    fn test_policies_prefer_better_children() {
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        let (good, bad) = (stats(80, 10, 10), stats(10, 80, 10));
+        for name in &["ucb1", "ucb1-tuned", "puct", "thompson"] {
+            let policy: Policy = name.parse().unwrap();
+            let good = policy.score(&mut rng, good, 200, 0.5);
+            let bad = policy.score(&mut rng, bad, 200, 0.5);
+            assert!(good > bad, "{} scored {} against {}", name, good, bad);
+        }
+    }
+
+    #[test]
+    fn test_puct_follows_priors() {
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        let policy = PUCT { c: 1.5 };
+        assert!(!policy.tries_all_first());
+        let likely = policy.score(&mut rng, Stats::ZERO, 10, 0.8);
+        let unlikely = policy.score(&mut rng, Stats::ZERO, 10, 0.2);
+        assert!(likely > unlikely);
+    }
+
+    #[test]
+    fn test_rave_weight_decays() {
+        for rave in &[Rave::Equivalence(100.0), Rave::MinimumMSE(0.1)] {
+            assert_eq!(rave.weight(0, 50), 1.0);
+            let weights: Vec<_> = [10, 100, 1000].iter().map(|&n| rave.weight(n, 2 * n)).collect();
+            assert!(weights[0] > weights[1] && weights[1] > weights[2]);
+        }
+        assert_eq!(Rave::Equivalence(100.0).weight(100, 200), 0.5);
+        // A child that looks better by AMAF than on its own gets a boost.
+        let rave = Rave::Equivalence(100.0);
+        assert!(rave.adjustment(stats(1, 9, 0), stats(8, 2, 0)) > 0.0);
+        assert_eq!(rave.adjustment(stats(1, 9, 0), Stats::ZERO), 0.0);
+    }
+}
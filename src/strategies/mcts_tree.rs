@@ -0,0 +1,591 @@
+use super::mcts_core::{self, Backend, Budget, Candidate, Entry, MemoryLimit, Size, Stats, StatsTable};
+use super::mcts_selection::SelectionPolicy;
+use super::rollout::{RolloutPolicy, Uniform};
+use game::*;
+use rand::{Rng, XorShiftRng};
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::mem;
+use std::mem::size_of;
+use std::sync::*;
+
+pub use super::mcts_core::MCTSParams;
+
+/// MCTS over an explicit tree, run on the calling thread and playing out new
+/// positions with `P`.
+pub type MCTS<G, P = Uniform> = mcts_core::MCTS<G, Tree<G, P>>;
+
+/// Where a node sits in its arena.
+pub type NodeId = usize;
+
+/// A move out of a node, with the results of the simulations that made it
+/// from the point of view of the player making it.
+#[derive(Clone)]
+pub struct Edge<G: Game> {
+    pub m: G::Move,
+    pub stats: Stats,
+    /// Simulations from the node in which the player made the move at any
+    /// later point, for RAVE.
+    pub amaf: Stats,
+    /// The node the move leads to, once a simulation has made it.
+    pub child: Option<NodeId>,
+}
+
+/// A position in the tree.  Only the root's position is kept: the others
+/// come from playing the moves on the edges down to them.
+#[derive(Clone)]
+pub struct Node<G: Game> {
+    pub to_act: G::Agent,
+    /// Simulations through the position, from the point of view of the
+    /// player to act.
+    pub stats: Stats,
+    /// The result with best play, once the search has proven it, for the
+    /// player to act.
+    pub proven: Option<Value>,
+    /// Every legal move, filled in the first time a simulation walks on
+    /// from the node.
+    pub edges: Vec<Edge<G>>,
+}
+
+/// The search tree, with every node in one arena and the root at index 0.
+/// Walking down follows edges rather than hashing positions, except to look
+/// up transpositions when they're on.
+pub struct Arena<G: Game> {
+    root: Option<G>,
+    nodes: Vec<Node<G>>,
+    // Edges out of every node put together.
+    edges: usize,
+    // Nodes by position, when positions reached by different move orders
+    // share a node.
+    transpositions: Option<HashMap<G, NodeId>>,
+}
+
+// Buffers a simulation reuses from one run to the next.
+struct Buffers<G: Game> {
+    moves: Vec<G::Move>,
+    // Every move made in the simulation and who made it, for RAVE.
+    played: Vec<(G::Agent, G::Move)>,
+    // The nodes the walk went through and the edge it took from each.
+    path: Vec<(NodeId, usize)>,
+}
+
+impl<G: Game + Hash + Eq> Arena<G> {
+    fn new(transpositions: bool) -> Self {
+        Arena {
+            root: None,
+            nodes: Vec::new(),
+            edges: 0,
+            transpositions: if transpositions {
+                Some(HashMap::new())
+            } else {
+                None
+            },
+        }
+    }
+
+    /// The position at the root, unless nothing has been searched yet.
+    pub fn root(&self) -> Option<&G> {
+        self.root.as_ref()
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node<G> {
+        &self.nodes[id]
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// How much the tree takes up, not counting anything the positions in
+    /// the transposition map keep on the heap.
+    pub fn size(&self) -> Size {
+        let positions = self.transpositions.as_ref().map(|t| t.len()).unwrap_or(0);
+        Size {
+            nodes: self.nodes.len(),
+            bytes: self.nodes.len() * size_of::<Node<G>>() + self.edges * size_of::<Edge<G>>()
+                + positions * (size_of::<G>() + size_of::<NodeId>()),
+        }
+    }
+
+    /// The node for `g`, if it's in the tree.  Without transpositions this
+    /// walks down from the root through the positions `g` can come from.
+    pub fn find(&self, g: &G) -> Option<NodeId> {
+        if let Some(ref t) = self.transpositions {
+            return t.get(g).cloned();
+        }
+        let root = match self.root {
+            Some(ref root) => root,
+            None => return None,
+        };
+        if root == g {
+            return Some(0);
+        }
+        let mut stack = vec![(0, root.clone())];
+        while let Some((id, game)) = stack.pop() {
+            for edge in &self.nodes[id].edges {
+                if let Some(child) = edge.child {
+                    let mut next = game.clone();
+                    next.apply(edge.m);
+                    if next == *g {
+                        return Some(child);
+                    }
+                    if next.reachable(g) {
+                        stack.push((child, next));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    // Adds a node for `game`, or finds the one already there if
+    // transpositions are on.
+    fn add(&mut self, game: &G, moves: &mut Vec<G::Move>) -> NodeId {
+        if let Some(&id) = self.transpositions.as_ref().and_then(|t| t.get(game)) {
+            return id;
+        }
+        game.legal_moves(moves);
+        let proven = if moves.is_empty() {
+            Some(Value::of(game.outcome(), game.to_act()))
+        } else {
+            None
+        };
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            to_act: game.to_act(),
+            stats: Stats::ZERO,
+            proven: proven,
+            edges: Vec::new(),
+        });
+        if let Some(ref mut t) = self.transpositions {
+            t.insert(game.clone(), id);
+        }
+        id
+    }
+
+    /// Moves the root to `root`, keeping only the nodes under it, or starts
+    /// over if it isn't in the tree.
+    fn reroot(&mut self, root: &G, moves: &mut Vec<G::Move>) {
+        if self.root.as_ref() == Some(root) {
+            return;
+        }
+        match self.find(root) {
+            Some(start) => self.rebuild(start, root.clone(), &[]),
+            None => {
+                self.nodes.clear();
+                self.edges = 0;
+                if let Some(ref mut t) = self.transpositions {
+                    t.clear();
+                }
+                self.root = Some(root.clone());
+                self.add(root, moves);
+            }
+        }
+    }
+
+    // Rebuilds the arena from the nodes under `start`, whose position is
+    // `root`, leaving out those marked in `evicted` and numbering the rest
+    // afresh.
+    fn rebuild(&mut self, start: NodeId, root: G, evicted: &[bool]) {
+        let old = mem::replace(&mut self.nodes, Vec::new());
+        if let Some(ref mut t) = self.transpositions {
+            t.clear();
+        }
+        self.edges = 0;
+        let mut ids: Vec<Option<NodeId>> = vec![None; old.len()];
+        let mut queue = VecDeque::new();
+        ids[start] = Some(0);
+        self.nodes.push(old[start].clone());
+        queue.push_back((start, root.clone()));
+        while let Some((id, game)) = queue.pop_front() {
+            self.edges += old[id].edges.len();
+            if let Some(ref mut t) = self.transpositions {
+                t.insert(game.clone(), ids[id].unwrap());
+            }
+            for edge in &old[id].edges {
+                if let Some(child) = edge.child {
+                    if ids[child].is_none() && !evicted.get(child).cloned().unwrap_or(false) {
+                        ids[child] = Some(self.nodes.len());
+                        self.nodes.push(old[child].clone());
+                        let mut next = game.clone();
+                        next.apply(edge.m);
+                        queue.push_back((child, next));
+                    }
+                }
+            }
+        }
+        for node in &mut self.nodes {
+            for edge in &mut node.edges {
+                edge.child = edge.child.and_then(|c| ids[c]);
+            }
+        }
+        self.root = Some(root);
+    }
+
+    /// Evicts leaves until there are `target` nodes left, the least visited
+    /// first and, among those, the furthest from the root.  Only leaves as
+    /// they stand are evicted, so it can take more than one round.  The
+    /// edges into them keep their stats.
+    fn evict(&mut self, target: usize) {
+        let root = match self.root.clone() {
+            Some(root) => root,
+            None => return,
+        };
+        let mut depths = vec![None; self.nodes.len()];
+        let mut queue = VecDeque::new();
+        let mut leaves = Vec::new();
+        depths[0] = Some(0);
+        queue.push_back(0);
+        while let Some(id) = queue.pop_front() {
+            let depth = depths[id].unwrap();
+            let mut leaf = true;
+            for edge in &self.nodes[id].edges {
+                if let Some(child) = edge.child {
+                    leaf = false;
+                    if depths[child].is_none() {
+                        depths[child] = Some(depth + 1);
+                        queue.push_back(child);
+                    }
+                }
+            }
+            if leaf && id != 0 {
+                leaves.push((self.nodes[id].stats.visits, depth, id));
+            }
+        }
+        leaves.sort_by_key(|&(visits, depth, _)| (visits, cmp::Reverse(depth)));
+        let mut evicted = vec![false; self.nodes.len()];
+        for (_, _, id) in leaves.into_iter().take(self.nodes.len().saturating_sub(target)) {
+            evicted[id] = true;
+        }
+        self.rebuild(0, root, &evicted);
+    }
+
+    // Runs one simulation from the root, like `mcts_core::simulate` but
+    // keeping the results on the tree's edges and nodes.
+    fn simulate<P, R>(&mut self, params: &MCTSParams, rollout: &mut P, rng: &mut R, buf: &mut Buffers<G>) -> Value
+    where
+        G: RandGame,
+        P: RolloutPolicy<G>,
+        R: Rng,
+    {
+        let record = params.rave.is_some();
+        buf.path.clear();
+        buf.played.clear();
+        let mut game = self.root.clone().expect("Searching an empty tree.");
+        let mut node = 0;
+        // The result, and whose result it is.
+        let (value, seen_by) = loop {
+            let acting = self.nodes[node].to_act;
+            if let Some(value) = self.nodes[node].proven {
+                break (value, acting);
+            }
+            if self.nodes[node].stats.visits == 0 {
+                let played = if record { Some(&mut buf.played) } else { None };
+                break (rollout.play_out(game, rng, &mut buf.moves, played), acting);
+            }
+            if self.nodes[node].edges.is_empty() {
+                game.legal_moves(&mut buf.moves);
+                self.nodes[node].edges = buf.moves
+                    .iter()
+                    .map(|&m| {
+                        Edge {
+                            m: m,
+                            stats: Stats::ZERO,
+                            amaf: Stats::ZERO,
+                            child: None,
+                        }
+                    })
+                    .collect();
+                self.edges += buf.moves.len();
+            }
+            let e = self.select(params, rng, &game, node);
+            let m = self.nodes[node].edges[e].m;
+            if record {
+                buf.played.push((acting, m));
+            }
+            buf.path.push((node, e));
+            game.apply(m);
+            node = match self.nodes[node].edges[e].child {
+                Some(child) => child,
+                None => {
+                    let child = self.add(&game, &mut buf.moves);
+                    self.nodes[node].edges[e].child = Some(child);
+                    child
+                }
+            };
+        };
+
+        if record {
+            for (i, &(id, _)) in buf.path.iter().enumerate() {
+                self.credit_amaf(id, &buf.played[i..], value, seen_by);
+            }
+        }
+        for &(id, e) in &buf.path {
+            let result = Stats::single(value.seen_by(seen_by, self.nodes[id].to_act));
+            self.nodes[id].stats += result;
+            self.nodes[id].edges[e].stats += result;
+        }
+        let result = Stats::single(value.seen_by(seen_by, self.nodes[node].to_act));
+        self.nodes[node].stats += result;
+
+        // Work up the path while the results below settle each node.
+        if self.nodes[node].proven.is_some() {
+            for &(id, _) in buf.path.iter().rev() {
+                match self.settle(id) {
+                    Some(value) => self.nodes[id].proven = Some(value),
+                    None => break,
+                }
+            }
+        }
+        value.seen_by(seen_by, self.nodes[0].to_act)
+    }
+
+    fn select<R: Rng>(&self, params: &MCTSParams, rng: &mut R, game: &G, id: NodeId) -> usize
+    where
+        G: RandGame,
+    {
+        let node = &self.nodes[id];
+        let uses_priors = params.policy.uses_priors();
+        let total_prior: f64 = if uses_priors {
+            node.edges.iter().map(|e| game.move_prior(&e.m)).sum()
+        } else {
+            1.0
+        };
+        let candidates = node.edges.iter().enumerate().map(|(i, e)| {
+            let proven = e.child.and_then(|c| {
+                let child = &self.nodes[c];
+                child.proven.map(|v| v.seen_by(child.to_act, node.to_act))
+            });
+            Candidate {
+                m: i,
+                stats: e.stats,
+                amaf: e.amaf,
+                proven: proven,
+                prior: if uses_priors {
+                    game.move_prior(&e.m) / total_prior
+                } else {
+                    1.0
+                },
+            }
+        });
+        mcts_core::choose(params, rng, node.stats.visits, candidates).expect("Selecting from a position with no moves.")
+    }
+
+    // The result of `id` with best play, if its children settle it: a win
+    // if any move wins, even with others not tried yet, and otherwise known
+    // once every move is.
+    fn settle(&self, id: NodeId) -> Option<Value> {
+        let acting = self.nodes[id].to_act;
+        let mut value = Some(Value::Loss);
+        for edge in &self.nodes[id].edges {
+            let child = match edge.child {
+                Some(c) => &self.nodes[c],
+                None => {
+                    value = None;
+                    continue;
+                }
+            };
+            match child.proven.map(|v| v.seen_by(child.to_act, acting)) {
+                Some(Value::Win) => return Some(Value::Win),
+                Some(v) => value = value.map(|value| cmp::max(value, v)),
+                None => value = None,
+            }
+        }
+        value
+    }
+
+    // Credits each edge out of `id` whose move its player went on to make
+    // in `played`, counting only the first time each move was made.
+    fn credit_amaf(&mut self, id: NodeId, played: &[(G::Agent, G::Move)], value: Value, seen_by: G::Agent) {
+        let acting = self.nodes[id].to_act;
+        let result = Stats::single(value.seen_by(seen_by, acting));
+        for (j, &(agent, m)) in played.iter().enumerate() {
+            if agent != acting || played[..j].contains(&(agent, m)) {
+                continue;
+            }
+            if let Some(edge) = self.nodes[id].edges.iter_mut().find(|e| e.m == m) {
+                if edge.stats.visits > 0 {
+                    edge.amaf += result;
+                }
+            }
+        }
+    }
+}
+
+struct State<G: Game, P> {
+    rollout: P,
+    rng: XorShiftRng,
+    buffers: Buffers<G>,
+}
+
+/// Runs simulations one after another over a tree kept in an arena, with
+/// the results on its edges.  Selection never hashes a position, and
+/// transpositions are only looked up as nodes are added, if
+/// `params.transpositions` is set.  Leaves are evicted between batches to
+/// keep within the memory limit.
+pub struct Tree<G: Game, P = Uniform> {
+    params: MCTSParams,
+    arena: Mutex<Arena<G>>,
+    state: Mutex<State<G, P>>,
+}
+
+impl<G: Game + Hash + Eq, P> Tree<G, P> {
+    /// The tree as it stands, for walking.  Searches wait while it's held.
+    pub fn arena<'a>(&'a self) -> MutexGuard<'a, Arena<G>> {
+        self.arena.lock().expect("Lock poisoned")
+    }
+}
+
+/// AMAF results live on the edges, so entries looked up by position don't
+/// have them.
+impl<G: Game + Hash + Eq, P> StatsTable<G> for Tree<G, P> {
+    fn lookup(&self, g: &G) -> Option<Entry> {
+        let arena = self.arena();
+        arena.find(g).map(|id| {
+            let node = arena.node(id);
+            Entry {
+                stats: node.stats,
+                proven: node.proven,
+                ..Entry::ZERO
+            }
+        })
+    }
+}
+
+impl<G: RandGame + Hash + Eq, P: RolloutPolicy<G>> Backend<G> for Tree<G, P> {
+    type Rollout = P;
+
+    fn create(params: MCTSParams, rollout: P) -> Self {
+        Tree {
+            params: params,
+            arena: Mutex::new(Arena::new(params.transpositions)),
+            state: Mutex::new(State {
+                rollout: rollout,
+                rng: seeded_rng(params.seed, 0),
+                buffers: Buffers {
+                    moves: Vec::new(),
+                    played: Vec::new(),
+                    path: Vec::new(),
+                },
+            }),
+        }
+    }
+
+    fn search(&self, root: &G, mut budget: Budget) {
+        while let Some(n) = budget.next_batch() {
+            let mut state = self.state.lock().expect("Lock poisoned");
+            let state = &mut *state;
+            let mut arena = self.arena();
+            arena.reroot(root, &mut state.buffers.moves);
+            for _ in 0..n {
+                arena.simulate(&self.params, &mut state.rollout, &mut state.rng, &mut state.buffers);
+            }
+            if let Some(target) = MemoryLimit::target(self.params.memory_limit, arena.size()) {
+                arena.evict(target);
+            }
+        }
+    }
+
+    fn prune(&self, root: &G) {
+        let mut moves = Vec::new();
+        self.arena().reroot(root, &mut moves);
+    }
+
+    fn size(&self) -> Size {
+        self.arena().size()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::Strategy;
+    use game::connectfour::{Color, ConnectFour};
+    use strategies::mcts_core::test::{play_moves, sim_params};
+
+    #[test]
+    fn test_keeps_the_subtree_under_the_new_root() {
+        let game = ConnectFour::new(&Color::R);
+        let mut strategy = MCTS::<ConnectFour>::create(sim_params(500));
+        let m = strategy.decide(&game);
+        assert!(game.move_valid(&m));
+        assert_eq!(strategy.backend().stats(&game).map(|s| s.visits), Some(500));
+        // One node at most for each simulation, reached through the edges.
+        let size = strategy.backend().arena().len();
+        assert!(size > 1 && size <= 500);
+        let edge_visits: usize = strategy.backend().arena().node(0).edges.iter().map(|e| e.stats.visits).sum();
+        assert_eq!(edge_visits, 499);
+
+        let after = play_moves(&game, &[m.0, 3]);
+        let kept = strategy.backend().stats(&after).map(|s| s.visits).unwrap_or(0);
+        strategy.decide(&after);
+        assert_eq!(strategy.backend().stats(&game), None);
+        assert_eq!(strategy.backend().stats(&after).map(|s| s.visits), Some(kept + 500));
+        assert_eq!(strategy.backend().arena().root(), Some(&after));
+    }
+
+    #[test]
+    fn test_proves_a_win() {
+        // R has three along the bottom and wins in column 3.
+        let game = play_moves(&ConnectFour::new(&Color::R), &[0, 6, 1, 6, 2, 5]);
+        let mut strategy = MCTS::<ConnectFour>::create(sim_params(200));
+        assert_eq!(strategy.decide(&game), (3, Color::R));
+        assert_eq!(strategy.backend().lookup(&game).and_then(|e| e.proven), Some(Value::Win));
+    }
+
+    #[test]
+    fn test_evicts_leaves_to_stay_within_memory_limit() {
+        let game = ConnectFour::new(&Color::R);
+        let mut strategy = MCTS::<ConnectFour>::create(MCTSParams {
+            batch_size: 20,
+            memory_limit: Some(MemoryLimit::Nodes(200)),
+            ..sim_params(2000)
+        });
+        strategy.decide(&game);
+        let arena = strategy.backend().arena();
+        assert!(arena.len() <= 200, "{:?}", arena.size());
+        // The root keeps every simulation, and the edges out of it keep
+        // theirs even where the node below was evicted.
+        assert_eq!(arena.node(0).stats.visits, 2000);
+        let edge_visits: usize = arena.node(0).edges.iter().map(|e| e.stats.visits).sum();
+        assert_eq!(edge_visits, 1999);
+        for id in 0..arena.len() {
+            assert!(arena.node(id).edges.iter().all(|e| e.child.map(|c| c < arena.len()).unwrap_or(true)));
+        }
+    }
+
+    #[test]
+    fn test_transpositions_share_nodes() {
+        let root = ConnectFour::new(&Color::R);
+        let (a, b) = (play_moves(&root, &[0, 1, 2]), play_moves(&root, &[2, 1, 0]));
+        let mut moves = Vec::new();
+        for &shared in &[true, false] {
+            let mut arena = Arena::new(shared);
+            arena.reroot(&root, &mut moves);
+            let (x, y) = (arena.add(&a, &mut moves), arena.add(&b, &mut moves));
+            assert_eq!(x == y, shared);
+        }
+
+        let mut strategy = MCTS::<ConnectFour>::create(MCTSParams {
+            transpositions: true,
+            ..sim_params(2000)
+        });
+        strategy.decide(&root);
+        // Every node is only in the tree once, however many edges lead to it.
+        let arena = strategy.backend().arena();
+        let mut parents = vec![0; arena.len()];
+        for id in 0..arena.len() {
+            for edge in &arena.node(id).edges {
+                if let Some(child) = edge.child {
+                    parents[child] += 1;
+                }
+            }
+        }
+        assert!(parents.iter().any(|&p| p > 1));
+    }
+}
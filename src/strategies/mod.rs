@@ -1,12 +1,36 @@
 use game::Game;
 
+pub use self::analysis::{Analysis, RatedMove, Rating};
+
 pub trait Strategy<G: Game> {
     type Params;
-    fn decide(&mut self, &G) -> G::Move;
+
+    /// The move to play in `game`: the best one `analyze` finds.  Strategies
+    /// that can find it without rating every move override this.
+    fn decide(&mut self, game: &G) -> G::Move {
+        self.analyze(game).best().expect("No moves available from start position.")
+    }
     fn create(Self::Params) -> Self;
+
+    /// Searches `game` and reports what it made of every move, best first.
+    fn analyze(&mut self, game: &G) -> Analysis<G>;
+
+    /// Called with the position once we've moved, for strategies that keep
+    /// thinking while the opponent does.  It mustn't block, and the next call
+    /// to `decide` or `ponder` ends it.  Does nothing by default.
+    fn ponder(&mut self, _game: &G) {}
 }
 
+pub mod analysis;
+pub mod eval;
+pub mod rollout;
 pub mod negamax;
+pub mod solver;
+pub mod transposition;
+pub mod mcts_core;
+pub mod mcts_selection;
 pub mod mcts;
 pub mod mcts_parallel;
 pub mod mcts_rayon;
+pub mod mcts_ensemble;
+pub mod mcts_tree;
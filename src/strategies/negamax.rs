@@ -1,92 +1,434 @@
 use super::*;
-use game::{RandGame, Score};
+use super::eval::{Evaluator, MonteCarlo, WIN};
+use super::transposition::{Bound, TranspositionTable};
+use game::{ReversibleGame, Score};
+use std::cmp;
 use std::fmt;
-use rand::XorShiftRng;
-use rand;
+use std::mem;
+use std::time::{Duration, Instant};
 
 use std::hash::Hash;
-use std::collections::HashMap;
-struct NegamaxState<G: Hash> {
-    rng: XorShiftRng,
-    cache: HashMap<G, usize>,
+
+// Room above any real score to negate without overflow.
+const INFINITY: Score = WIN + 1;
+
+struct NegamaxState<G: Game> {
+    table: TranspositionTable<G::Move>,
+    deadline: Option<Instant>,
+    timed_out: bool,
+    // Positions searched since the last call to `analyze`.
+    nodes: usize,
+    // A move buffer for each depth left, reused by every node searched at
+    // that depth.
+    moves: Vec<Vec<G::Move>>,
 }
-pub struct NegamaxParams {
+
+pub struct NegamaxParams<E = MonteCarlo> {
+    // Deepest iteration to search to.
     pub max_depth: usize,
-    pub trials: usize,
+    // Time limit in ms.
+    pub timeout: u64,
+    // Number of transposition table entries.
+    pub table_size: usize,
+    pub evaluator: E,
+    // Seed for the evaluator's random numbers, if any, to play the same way
+    // again.  Searches that stop at the timeout still depend on timing.
+    pub seed: Option<[u32; 4]>,
 }
 
-pub struct Negamax<G: Hash> {
-    pub params: NegamaxParams,
+pub struct Negamax<G: Game, E = MonteCarlo> {
+    pub params: NegamaxParams<E>,
     state: NegamaxState<G>,
 }
 
 
 
-impl<G> Negamax<G>
+impl<G, E> Negamax<G, E>
 where
-    G: RandGame + Send + fmt::Display + Hash + Eq,
+    G: ReversibleGame + Send + fmt::Display + Hash + Eq,
     G::Agent: Send,
     G::Move: Send + Ord,
+    E: Evaluator<G>,
 {
-    fn heuristic(&mut self, game: G) -> usize {
-        if let Some(v) = self.state.cache.get(&game) {
-            return *v;
+    fn heuristic(&mut self, game: &G, has_moves: bool) -> Score {
+        match game.winner() {
+            Some(c) => if c == game.to_act() { WIN } else { -WIN },
+            None => if has_moves {
+                self.params.evaluator.evaluate(game)
+            } else {
+                0
+            },
         }
+    }
 
-        let trials = self.params.trials;
-        let ans;
-        if let Some(c) = game.winner() {
-            ans = if c == game.ref_player() { trials } else { 0 };
-        } else {
-            ans = game.clone().monte_carlo(&mut self.state.rng, trials as u32) as usize
+    fn out_of_time(&mut self) -> bool {
+        if !self.state.timed_out {
+            if let Some(deadline) = self.state.deadline {
+                self.state.timed_out = Instant::now() >= deadline;
+            }
         }
-        self.state.cache.insert(game, ans);
-        ans
+        self.state.timed_out
+    }
+
+    /// Alpha-beta search of `depth` more plies.  Scores are from the point of
+    /// view of the player to act.  Moves that leave the same player to act
+    /// (e.g. completing a box in dots) keep the window and sign as is.  If the
+    /// deadline passes mid search the returned value is meaningless and
+    /// `timed_out` is set.  Moves are made and taken back on `game`, which is
+    /// left as it was.
+    pub fn negamax(
+        &mut self,
+        game: &mut G,
+        depth: usize,
+        mut alpha: Score,
+        mut beta: Score,
+    ) -> (Score, Option<G::Move>) {
+        self.state.nodes += 1;
+        let original_alpha = alpha;
+        let key = TranspositionTable::<G::Move>::key(&*game);
+
+        let mut first = None;
+        if let Some(entry) = self.state.table.get(key) {
+            if entry.depth >= depth {
+                match entry.bound {
+                    Bound::Exact => return (entry.score, entry.best_move),
+                    Bound::Lower => alpha = cmp::max(alpha, entry.score),
+                    Bound::Upper => beta = cmp::min(beta, entry.score),
+                }
+                if alpha >= beta {
+                    return (entry.score, entry.best_move);
+                }
+            }
+            first = entry.best_move;
+        }
+
+        if self.state.moves.len() <= depth {
+            self.state.moves.resize(depth + 1, Vec::new());
+        }
+        // Taken out while the moves below use their own, and put back once
+        // the node is done.
+        let mut nexts = mem::replace(&mut self.state.moves[depth], Vec::new());
+        game.legal_moves(&mut nexts);
+        let best = loop {
+            if depth == 0 || nexts.is_empty() {
+                let score = self.heuristic(&*game, !nexts.is_empty());
+                self.state.table.insert(key, depth, score, Bound::Exact, None);
+                break (score, None);
+            }
+
+            if self.out_of_time() {
+                break (0, None);
+            }
 
+            // Search the best move from earlier searches first for better
+            // cutoffs.
+            if let Some(m) = first {
+                if let Some(i) = nexts.iter().position(|&mv| mv == m) {
+                    nexts.swap(0, i);
+                }
+            }
+
+            let mover = game.to_act();
+            let mut best = (-INFINITY, None);
+            for &m in nexts.iter() {
+                game.apply(m);
+                let s = if game.to_act() == mover {
+                    self.negamax(game, depth - 1, alpha, beta).0
+                } else {
+                    -self.negamax(game, depth - 1, -beta, -alpha).0
+                };
+                game.undo(m);
+                if self.state.timed_out {
+                    break;
+                }
+
+                if s > best.0 {
+                    best = (s, Some(m));
+                }
+                if best.0 > alpha {
+                    alpha = best.0;
+                }
+                if alpha >= beta {
+                    break;
+                }
+            }
+            if self.state.timed_out {
+                break (0, None);
+            }
+
+            let bound = if best.0 <= original_alpha {
+                Bound::Upper
+            } else if best.0 >= beta {
+                Bound::Lower
+            } else {
+                Bound::Exact
+            };
+            self.state.table.insert(key, depth, best.0, bound, best.1);
+            break best;
+        };
+        self.state.moves[depth] = nexts;
+        best
     }
 
-    pub fn negamax(&mut self, game: G, depth: usize) -> (Score, Option<G::Move>) {
-        let NegamaxParams { max_depth, .. } = self.params;
+    /// Iterative deepening up to `max_depth`, returning the best move of the
+    /// last iteration that finished before the deadline, and its depth.
+    fn search(&mut self, game: &G) -> (Option<G::Move>, usize) {
+        let start_time = Instant::now();
+        self.state.deadline = Some(start_time + Duration::from_millis(self.params.timeout));
+        self.state.timed_out = false;
+        self.state.table.new_search();
 
-        let nexts = game.possible_moves();
-        if depth > max_depth || nexts.is_empty() {
-            let weight = game.player_weight(&game.to_act());
-            return (self.heuristic(game) as Score * weight, None);
+        let mut game = game.clone();
+        let mut best = (None, 0);
+        for depth in 1..self.params.max_depth + 1 {
+            let (_, m) = self.negamax(&mut game, depth, -INFINITY, INFINITY);
+            if self.state.timed_out {
+                break;
+            }
+            best = (m, depth);
         }
-        nexts
+
+        // The first iteration may not finish under a very short timeout, but
+        // we still owe the caller a move.
+        if best.0.is_none() {
+            self.state.deadline = None;
+            self.state.timed_out = false;
+            let (_, m) = self.negamax(&mut game, 1, -INFINITY, INFINITY);
+            best = (m, 1);
+        }
+        best
+    }
+
+    /// Scores every move from `game` by a full-width search `depth` plies
+    /// deep, best first, with `best` ahead of any it ties with.  The table
+    /// is warm from the search, so this costs about one more iteration.  It
+    /// keeps to the search's deadline: once that passes only scores the table
+    /// already has are used, and the other moves go unrated.
+    fn rate_moves(&mut self, game: &G, depth: usize, best: Option<G::Move>) -> Vec<RatedMove<G::Move>> {
+        let mut game = game.clone();
+        let mover = game.to_act();
+        let mut moves = Vec::new();
+        game.legal_moves(&mut moves);
+        let mut rated: Vec<_> = moves
             .into_iter()
-            .map(|mv| {
-                let m = *mv.valid_move();
-                let new_game = mv.apply();
-                let (s, _) = self.negamax(new_game, depth + 1);
-                (-s, Some(m))
+            .map(|m| {
+                self.state.timed_out = false;
+                game.apply(m);
+                let s = if game.to_act() == mover {
+                    self.negamax(&mut game, depth - 1, -INFINITY, INFINITY).0
+                } else {
+                    -self.negamax(&mut game, depth - 1, -INFINITY, INFINITY).0
+                };
+                game.undo(m);
+                RatedMove {
+                    m: m,
+                    visits: 0,
+                    rating: if self.state.timed_out {
+                        None
+                    } else {
+                        Some(Rating::Score(s))
+                    },
+                }
             })
-            .max()
-            .unwrap()
+            .collect();
+        rated.sort_by_key(|r| {
+            let score = match r.rating {
+                Some(Rating::Score(s)) => s,
+                _ => -INFINITY,
+            };
+            (Some(r.m) != best, -score)
+        });
+        rated
+    }
+
+    /// The line of play the table expects from `game`, starting with `best`,
+    /// at most `depth` moves long.
+    fn principal_variation(&self, game: &G, depth: usize, best: Option<G::Move>) -> Vec<G::Move> {
+        let mut game = game.clone();
+        let mut pv = Vec::new();
+        let mut next = best;
+        while let Some(m) = next {
+            // Another position's entry can sit in the same slot.
+            if pv.len() >= depth || !game.move_valid(&m) {
+                break;
+            }
+            pv.push(m);
+            game.apply(m);
+            let key = TranspositionTable::<G::Move>::key(&game);
+            next = self.state.table.get(key).and_then(|e| e.best_move);
+        }
+        pv
     }
 }
 
-impl<G> Strategy<G> for Negamax<G>
+impl<G, E> Strategy<G> for Negamax<G, E>
 where
-    G: RandGame + Send + fmt::Display + Hash + Eq,
+    G: ReversibleGame + Send + fmt::Display + Hash + Eq,
     G::Agent: Send,
     G::Move: Send + Ord,
+    E: Evaluator<G>,
 {
-    type Params = NegamaxParams;
+    type Params = NegamaxParams<E>;
     fn decide(&mut self, game: &G) -> G::Move {
-        let (_, maybe_move) = self.negamax(game.clone(), 0);
-        self.state.cache.clear();
+        let (maybe_move, _) = self.search(game);
         maybe_move.expect("No moves available from start position.")
     }
-    fn create(params: NegamaxParams) -> Self {
-        let seed = rand::random::<[u32; 4]>();
-        let rng: XorShiftRng = rand::SeedableRng::from_seed(seed);
+    fn analyze(&mut self, game: &G) -> Analysis<G> {
+        let start = Instant::now();
+        self.state.nodes = 0;
+        let (best, depth) = self.search(game);
+        let moves = if best.is_some() {
+            self.rate_moves(game, depth, best)
+        } else {
+            Vec::new()
+        };
+        Analysis {
+            moves: moves,
+            pv: self.principal_variation(game, depth, best),
+            nodes: self.state.nodes,
+            elapsed: start.elapsed(),
+        }
+    }
+    fn create(mut params: NegamaxParams<E>) -> Self {
+        if let Some(seed) = params.seed {
+            params.evaluator.reseed(seed);
+        }
         Self {
-            params: params,
             state: NegamaxState {
-                rng: rng,
-                cache: HashMap::new(),
+                table: TranspositionTable::new(params.table_size),
+                deadline: None,
+                timed_out: false,
+                nodes: 0,
+                moves: Vec::new(),
             },
+            params: params,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::eval::ConnectFourEvaluator;
+    use game::connectfour::*;
+    use game::connectfour::Color::*;
+    use game::dots::*;
+    use game::dots::DotsMove::{H, V};
+
+    #[test]
+    fn test_takes_immediate_win() {
+        let mut game = ConnectFour::new(&R);
+        game.try_moves(vec![(3, R), (4, B), (3, R), (4, B), (3, R), (4, B)].into_iter());
+        let mut strategy = Negamax::create(NegamaxParams {
+            max_depth: 4,
+            timeout: 10000,
+            table_size: 1 << 16,
+            evaluator: ConnectFourEvaluator::default(),
+            seed: None,
+        });
+        assert_eq!(strategy.decide(&game), (3, R));
+
+        let analysis = strategy.analyze(&game);
+        assert_eq!(analysis.moves.len(), 7);
+        assert_eq!(analysis.moves[0].rating, Some(Rating::Score(WIN)));
+        assert!(analysis.moves[1..].iter().all(|r| r.rating != Some(Rating::Score(WIN))));
+        assert_eq!(analysis.pv, vec![(3, R)]);
+        assert!(analysis.nodes > 0);
+    }
+
+    #[test]
+    fn test_rating_moves_keeps_to_deadline() {
+        let game = ConnectFour::new(&R);
+        let mut strategy = Negamax::create(NegamaxParams {
+            max_depth: 2,
+            timeout: 10000,
+            table_size: 1 << 16,
+            evaluator: ConnectFourEvaluator::default(),
+            seed: None,
+        });
+        let (best, _) = strategy.search(&game);
+
+        // Too deep for the table to know, and no time left to search.
+        strategy.state.deadline = Some(Instant::now());
+        strategy.state.nodes = 0;
+        let rated = strategy.rate_moves(&game, 8, best);
+        assert_eq!(Some(rated[0].m), best);
+        assert!(rated.iter().all(|r| r.rating.is_none()));
+        assert_eq!(strategy.state.nodes, 7);
+    }
+
+    #[test]
+    fn test_reuses_move_buffers() {
+        let game = ConnectFour::new(&R);
+        let mut strategy = Negamax::create(NegamaxParams {
+            max_depth: 4,
+            timeout: 10000,
+            table_size: 1 << 16,
+            evaluator: ConnectFourEvaluator::default(),
+            seed: None,
+        });
+        strategy.decide(&game);
+        assert_eq!(strategy.state.moves.len(), 5);
+        let buffers: Vec<_> = strategy.state.moves.iter().map(|b| b.as_ptr()).collect();
+
+        // Every node at a depth fills the same buffer, so searching again
+        // doesn't allocate new ones.
+        let mut next = game.clone();
+        next.try_move((3, R));
+        strategy.decide(&next);
+        assert_eq!(strategy.state.moves.iter().map(|b| b.as_ptr()).collect::<Vec<_>>(), buffers);
+    }
+
+    fn dots_strategy() -> Negamax<Dots> {
+        Negamax::create(NegamaxParams {
+            max_depth: 12,
+            timeout: 10000,
+            table_size: 1 << 16,
+            evaluator: MonteCarlo::new(1),
+            seed: Some([1, 2, 3, 4]),
+        })
+    }
+
+    // B to act with a box up and four edges left.  V(1, 1) takes a box, and
+    // the extra move lets B take the next one with V(1, 2) too, winning 3-1.
+    // Scoring the extra move as the opponent's makes the capture look bad
+    // and picks H(0, 0) or V(0, 0), which hands A the other three boxes.
+    fn extra_turn_position() -> Dots {
+        let mut game = Dots::new(&DotsPlayer::A);
+        game.try_moves(
+            vec![V(1, 0), H(1, 1), H(2, 0), V(0, 2), H(0, 1), H(2, 1), H(1, 0), V(0, 1)].into_iter(),
+        );
+        game
+    }
+
+    #[test]
+    fn test_dots_takes_box_with_extra_turn() {
+        let mut game = extra_turn_position();
+        assert_eq!(game.to_act(), DotsPlayer::B);
+
+        let mut strategy = dots_strategy();
+        let (score, m) = strategy.negamax(&mut game, 12, -INFINITY, INFINITY);
+        assert_eq!(score, WIN);
+        assert_eq!(m, Some(V(1, 1)));
+        assert_eq!(strategy.decide(&game), V(1, 1));
+    }
+
+    #[test]
+    fn test_dots_scores_capture_chain_for_mover() {
+        let mut game = extra_turn_position();
+        game.try_move(V(1, 1));
+        // Still B, who takes another box before giving up the last one.
+        assert_eq!(game.to_act(), DotsPlayer::B);
+
+        let mut strategy = dots_strategy();
+        let (score, _) = strategy.negamax(&mut game, 12, -INFINITY, INFINITY);
+        assert_eq!(score, WIN);
+
+        // Declining the box leaves A to take the rest of the board.
+        let mut declined = extra_turn_position();
+        declined.try_move(H(0, 0));
+        assert_eq!(declined.to_act(), DotsPlayer::A);
+        let (score, _) = strategy.negamax(&mut declined, 12, -INFINITY, INFINITY);
+        assert_eq!(score, WIN);
+    }
+}
@@ -0,0 +1,332 @@
+use game::*;
+use rand::Rng;
+use super::eval::Evaluator;
+
+/// How playouts pick their moves.  Policies can keep scratch space, so each
+/// thread playing out positions needs its own.
+pub trait RolloutPolicy<G: RandGame> {
+    /// The move to play from `game`, or `None` if there are none.  `buf` is
+    /// scratch space.
+    fn choose<R: Rng>(&mut self, game: &G, rng: &mut R, buf: &mut Vec<G::Move>) -> Option<G::Move>;
+
+    /// Plays `game` out and returns the result for the player to act at the
+    /// start.  Every move played, along with who played it, is pushed onto
+    /// `played` if given.
+    fn play_out<R: Rng>(
+        &mut self,
+        mut game: G,
+        rng: &mut R,
+        buf: &mut Vec<G::Move>,
+        played: Option<&mut Vec<(G::Agent, G::Move)>>,
+    ) -> Value
+    where
+        Self: Sized,
+    {
+        let acting = game.to_act();
+        let outcome = play(self, &mut game, rng, buf, played, None).expect("Playout stopped early.");
+        Value::of(outcome, acting)
+    }
+}
+
+/// Plays moves chosen by `policy` until the game ends, returning how, or
+/// until `limit` moves have been played.
+fn play<G, P, R>(
+    policy: &mut P,
+    game: &mut G,
+    rng: &mut R,
+    buf: &mut Vec<G::Move>,
+    mut played: Option<&mut Vec<(G::Agent, G::Move)>>,
+    limit: Option<usize>,
+) -> Option<Outcome<G::Agent>>
+where
+    G: RandGame,
+    P: RolloutPolicy<G>,
+    R: Rng,
+{
+    let mut moves = 0;
+    loop {
+        if let Some(w) = game.winner() {
+            return Some(Outcome::Win(w));
+        }
+        if limit.map(|l| moves >= l).unwrap_or(false) {
+            return None;
+        }
+        match policy.choose(game, rng, buf) {
+            None => return Some(Outcome::Draw),
+            Some(m) => {
+                if let Some(ref mut played) = played {
+                    played.push((game.to_act(), m));
+                }
+                game.apply(m);
+                moves += 1;
+            }
+        }
+    }
+}
+
+/// How many of `trials` playouts of `game` by `policy` its reference player
+/// wins.
+pub fn monte_carlo<G, P, R>(game: &G, policy: &mut P, rng: &mut R, trials: u32) -> u32
+where
+    G: RandGame,
+    P: RolloutPolicy<G>,
+    R: Rng,
+{
+    let ref_player = game.ref_player();
+    let acting = game.to_act();
+    let mut buf = Vec::new();
+    (0..trials)
+        .map(|_| policy.play_out(game.clone(), rng, &mut buf, None))
+        .filter(|v| v.seen_by(acting, ref_player) == Value::Win)
+        .map(|_| 1)
+        .sum()
+}
+
+/// Uniformly random moves.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Uniform;
+
+impl<G: RandGame> RolloutPolicy<G> for Uniform {
+    fn choose<R: Rng>(&mut self, game: &G, rng: &mut R, buf: &mut Vec<G::Move>) -> Option<G::Move> {
+        game.choose_move(rng, buf)
+    }
+}
+
+/// Takes a win when there is one, and otherwise picks at random among the
+/// moves that don't let the opponent win straight away.  Moves after which
+/// the same player moves again count as safe.
+#[derive(Debug, Clone)]
+pub struct Tactical<G: Game> {
+    replies: Vec<G::Move>,
+}
+
+impl<G: Game> Default for Tactical<G> {
+    fn default() -> Self {
+        Tactical { replies: Vec::new() }
+    }
+}
+
+impl<G: RandGame> Tactical<G> {
+    // Whether the player to act in `game` can win with their next move.
+    fn threatens(&mut self, game: &G) -> bool {
+        let acting = game.to_act();
+        game.legal_moves(&mut self.replies);
+        self.replies.iter().any(|&m| {
+            let mut g = game.clone();
+            g.apply(m);
+            g.winner() == Some(acting)
+        })
+    }
+}
+
+impl<G: RandGame> RolloutPolicy<G> for Tactical<G> {
+    fn choose<R: Rng>(&mut self, game: &G, rng: &mut R, buf: &mut Vec<G::Move>) -> Option<G::Move> {
+        game.legal_moves(buf);
+        let acting = game.to_act();
+        let mut safe = 0;
+        let mut pick = None;
+        for &m in buf.iter() {
+            let mut g = game.clone();
+            g.apply(m);
+            if g.winner() == Some(acting) {
+                return Some(m);
+            }
+            // A move that keeps the turn, like completing a box in dots,
+            // leaves the opponent no reply to win with.
+            if g.to_act() == acting || !self.threatens(&g) {
+                safe += 1;
+                if rng.gen_range(0, safe) == 0 {
+                    pick = Some(m);
+                }
+            }
+        }
+        // With no safe moves, any will do.
+        pick.or_else(|| if buf.is_empty() {
+            None
+        } else {
+            Some(buf[rng.gen_range(0, buf.len())])
+        })
+    }
+}
+
+/// Plays a winning move if there is one, otherwise the move `eval` likes
+/// best, or a random one with probability `epsilon`.
+#[derive(Debug, Clone)]
+pub struct EpsilonGreedy<E> {
+    pub epsilon: f64,
+    pub eval: E,
+}
+
+impl<E: Default> Default for EpsilonGreedy<E> {
+    fn default() -> Self {
+        EpsilonGreedy {
+            epsilon: 0.1,
+            eval: E::default(),
+        }
+    }
+}
+
+impl<G: RandGame, E: Evaluator<G>> RolloutPolicy<G> for EpsilonGreedy<E> {
+    fn choose<R: Rng>(&mut self, game: &G, rng: &mut R, buf: &mut Vec<G::Move>) -> Option<G::Move> {
+        if rng.gen::<f64>() < self.epsilon {
+            return game.choose_move(rng, buf);
+        }
+        game.legal_moves(buf);
+        let acting = game.to_act();
+        let mut ties = 0;
+        let mut best: Option<(Score, G::Move)> = None;
+        for &m in buf.iter() {
+            let mut g = game.clone();
+            g.apply(m);
+            if g.winner() == Some(acting) {
+                return Some(m);
+            }
+            let score = self.eval.evaluate(&g);
+            let score = if g.to_act() == acting { score } else { -score };
+            match best {
+                Some((b, _)) if score < b => continue,
+                Some((b, _)) if score == b => {
+                    // Break ties at random.
+                    ties += 1;
+                    if rng.gen_range(0, ties + 1) != 0 {
+                        continue;
+                    }
+                }
+                _ => ties = 0,
+            }
+            best = Some((score, m));
+        }
+        best.map(|(_, m)| m)
+    }
+}
+
+/// Plays up to `depth` moves with `policy`, then scores the position with
+/// `eval` instead of playing on: a win for whoever it favors, or a draw if
+/// neither.
+#[derive(Debug, Clone)]
+pub struct Cutoff<P, E> {
+    pub depth: usize,
+    pub policy: P,
+    pub eval: E,
+}
+
+impl<P: Default, E: Default> Default for Cutoff<P, E> {
+    fn default() -> Self {
+        Cutoff {
+            depth: 20,
+            policy: P::default(),
+            eval: E::default(),
+        }
+    }
+}
+
+impl<G, P, E> RolloutPolicy<G> for Cutoff<P, E>
+where
+    G: RandGame,
+    P: RolloutPolicy<G>,
+    E: Evaluator<G>,
+{
+    fn choose<R: Rng>(&mut self, game: &G, rng: &mut R, buf: &mut Vec<G::Move>) -> Option<G::Move> {
+        self.policy.choose(game, rng, buf)
+    }
+
+    fn play_out<R: Rng>(
+        &mut self,
+        mut game: G,
+        rng: &mut R,
+        buf: &mut Vec<G::Move>,
+        played: Option<&mut Vec<(G::Agent, G::Move)>>,
+    ) -> Value {
+        let acting = game.to_act();
+        match play(&mut self.policy, &mut game, rng, buf, played, Some(self.depth)) {
+            Some(outcome) => Value::of(outcome, acting),
+            None => {
+                let score = self.eval.evaluate(&game);
+                let value = if score > 0 {
+                    Value::Win
+                } else if score < 0 {
+                    Value::Loss
+                } else {
+                    Value::Draw
+                };
+                value.seen_by(game.to_act(), acting)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use game::connectfour::{Color, ConnectFour};
+    use game::dots::{Dots, DotsPlayer};
+    use game::dots::DotsMove::{H, V};
+    use rand::{SeedableRng, XorShiftRng};
+    use strategies::eval::ConnectFourEvaluator;
+    use strategies::mcts_core::test::play_moves;
+
+    #[test]
+    fn test_tactical_wins_and_blocks() {
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        let mut policy = Tactical::default();
+        let mut buf = Vec::new();
+
+        // R has three along the bottom and wins in column 3.
+        let game = play_moves(&ConnectFour::new(&Color::R), &[0, 6, 1, 6, 2, 5]);
+        for _ in 0..10 {
+            assert_eq!(policy.choose(&game, &mut rng, &mut buf), Some((3, Color::R)));
+        }
+
+        // B has three along the bottom, so R must block in column 3.
+        let game = play_moves(&ConnectFour::new(&Color::R), &[6, 0, 6, 1, 5, 2]);
+        for _ in 0..10 {
+            assert_eq!(policy.choose(&game, &mut rng, &mut buf), Some((3, Color::R)));
+        }
+    }
+
+    #[test]
+    fn test_playouts_reach_a_result() {
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        let game = play_moves(&ConnectFour::new(&Color::R), &[0, 6, 1, 6, 2, 5]);
+        let mut buf = Vec::new();
+        let mut played = Vec::new();
+
+        let mut greedy = EpsilonGreedy {
+            epsilon: 0.0,
+            eval: ConnectFourEvaluator::default(),
+        };
+        assert_eq!(greedy.play_out(game.clone(), &mut rng, &mut buf, Some(&mut played)), Value::Win);
+        assert_eq!(played, vec![(Color::R, (3, Color::R))]);
+
+        // With no moves to play the position is scored as it stands, and
+        // R's open three favors R.
+        let mut cutoff: Cutoff<Uniform, ConnectFourEvaluator> = Cutoff {
+            depth: 0,
+            ..Default::default()
+        };
+        assert_eq!(cutoff.play_out(game.clone(), &mut rng, &mut buf, None), Value::Win);
+
+        let wins = monte_carlo(&game, &mut Tactical::default(), &mut rng, 20);
+        assert_eq!(wins, 20);
+    }
+
+    #[test]
+    fn test_tactical_keeps_the_turn_in_dots() {
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        let mut policy = Tactical::default();
+        let mut buf = Vec::new();
+
+        // Two edges left: H(1, 1) takes a box and moves again, while V(0, 2)
+        // hands A the last box and the game.
+        let mut game = Dots::new(&DotsPlayer::A);
+        game.try_moves(
+            vec![H(2, 1), V(0, 1), V(1, 0), H(2, 0), V(1, 2), V(0, 0), V(1, 1), H(1, 0), H(0, 0), H(0, 1)]
+                .into_iter(),
+        );
+        game.legal_moves(&mut buf);
+        assert_eq!(buf, vec![H(1, 1), V(0, 2)]);
+        for _ in 0..10 {
+            assert_eq!(policy.choose(&game, &mut rng, &mut buf), Some(H(1, 1)));
+        }
+    }
+}
@@ -0,0 +1,594 @@
+use super::*;
+use super::transposition::{Bound, TranspositionTable};
+use game::Value;
+use game::connectfour::ConnectFour;
+use std::cmp;
+use std::time::Instant;
+
+/// Exact value of a position for the player to act, with the number of
+/// plies left until the game ends under perfect play.  Winners win as fast
+/// as they can and losers hold out as long as they can.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Solution {
+    Win(usize),
+    Loss(usize),
+    Draw(usize),
+}
+
+impl Solution {
+    /// The same result seen by the player who made the move leading here.
+    fn before_move(self) -> Solution {
+        match self {
+            Solution::Win(n) => Solution::Loss(n + 1),
+            Solution::Loss(n) => Solution::Win(n + 1),
+            Solution::Draw(n) => Solution::Draw(n + 1),
+        }
+    }
+
+    /// The same result seen by the player to act once the move is made.
+    fn after_move(self) -> Solution {
+        match self {
+            Solution::Win(n) => Solution::Loss(n - 1),
+            Solution::Loss(n) => Solution::Win(n - 1),
+            Solution::Draw(n) => Solution::Draw(n - 1),
+        }
+    }
+
+    /// The result, without how long it takes.
+    pub fn value(&self) -> Value {
+        match *self {
+            Solution::Win(_) => Value::Win,
+            Solution::Loss(_) => Value::Loss,
+            Solution::Draw(_) => Value::Draw,
+        }
+    }
+
+    // Larger is better for the player to act.
+    fn rank(&self) -> (i32, i64) {
+        match *self {
+            Solution::Win(n) => (2, -(n as i64)),
+            Solution::Draw(_) => (1, 0),
+            Solution::Loss(n) => (0, n as i64),
+        }
+    }
+}
+
+/// Bitboard position from the point of view of the player to act, using
+/// the same layout as `ConnectFour`: columns from the bottom with a spare bit
+/// on top of each.
+#[derive(Clone, Copy)]
+struct Position {
+    current: u64,
+    mask: u64,
+    moves: usize,
+    width: usize,
+    height: usize,
+    needed: usize,
+    // The bottom cell of every column.
+    bottom: u64,
+    // Every cell on the board.
+    board: u64,
+}
+
+fn shr(bits: u64, n: usize) -> u64 {
+    bits.checked_shr(n as u32).unwrap_or(0)
+}
+
+fn shl(bits: u64, n: usize) -> u64 {
+    bits.checked_shl(n as u32).unwrap_or(0)
+}
+
+impl Position {
+    fn from_game(game: &ConnectFour) -> Self {
+        let (width, height) = (game.width(), game.height());
+        let mut pos = Position {
+            current: 0,
+            mask: 0,
+            moves: 0,
+            width: width,
+            height: height,
+            needed: game.needed(),
+            bottom: 0,
+            board: 0,
+        };
+
+        let to_act = game.to_act();
+        for j in 0..width {
+            pos.bottom |= pos.bit(0, j);
+            pos.board |= pos.column_mask(j);
+            for i in 0..height {
+                if let Some(c) = game.get(i, j) {
+                    pos.mask |= pos.bit(i, j);
+                    pos.moves += 1;
+                    if c == to_act {
+                        pos.current |= pos.bit(i, j);
+                    }
+                }
+            }
+        }
+        pos
+    }
+
+    fn size(&self) -> usize {
+        self.width * self.height
+    }
+
+    fn bit(&self, i: usize, j: usize) -> u64 {
+        1 << (j * (self.height + 1) + i)
+    }
+
+    fn column_mask(&self, j: usize) -> u64 {
+        ((1 << self.height) - 1) << (j * (self.height + 1))
+    }
+
+    fn key(&self) -> u64 {
+        self.current + self.mask
+    }
+
+    fn possible(&self) -> u64 {
+        self.mask.wrapping_add(self.bottom) & self.board
+    }
+
+    /// Empty cells that would complete a line for `bits`.
+    fn winning_cells(&self, bits: u64) -> u64 {
+        let height = self.height;
+        let needed = self.needed;
+        let mut cells = 0;
+        // Vertical, horizontal and the two diagonals, with the gap at each
+        // place along the line.
+        for &shift in &[1, height + 1, height, height + 2] {
+            for gap in 0..needed {
+                let mut line = !0;
+                for k in 0..needed {
+                    if k > gap {
+                        line &= shr(bits, (k - gap) * shift);
+                    } else if k < gap {
+                        line &= shl(bits, (gap - k) * shift);
+                    }
+                }
+                cells |= line;
+            }
+        }
+        cells & (self.board ^ self.mask)
+    }
+
+    fn can_win_next(&self) -> bool {
+        self.winning_cells(self.current) & self.possible() != 0
+    }
+
+    /// Moves that don't let the opponent win straight away.  Empty if every
+    /// move loses next turn.
+    fn non_losing_moves(&self) -> u64 {
+        let mut possible = self.possible();
+        let opponent_wins = self.winning_cells(self.current ^ self.mask);
+        let forced = possible & opponent_wins;
+        if forced != 0 {
+            if forced & (forced - 1) != 0 {
+                // Two threats, only one can be blocked.
+                return 0;
+            }
+            possible = forced;
+        }
+        // Don't play right under one of the opponent's winning cells.
+        possible & !(opponent_wins >> 1)
+    }
+
+    fn move_score(&self, m: u64) -> u32 {
+        self.winning_cells(self.current | m).count_ones()
+    }
+
+    fn play(&mut self, m: u64) {
+        self.current ^= self.mask;
+        self.mask |= m;
+        self.moves += 1;
+    }
+
+    /// The column that `m` plays in.
+    fn column(&self, m: u64) -> usize {
+        m.trailing_zeros() as usize / (self.height + 1)
+    }
+
+    /// Converts a result back into a score, the inverse of `solution`.
+    fn score(&self, solution: Solution) -> i32 {
+        let size = self.size() as i32;
+        let n = self.moves as i32;
+        match solution {
+            Solution::Win(plies) => (size + 1 - n) / 2 + 1 - (plies as i32 + 1) / 2,
+            Solution::Loss(plies) => plies as i32 / 2 - 1 - (size - n) / 2,
+            Solution::Draw(_) => 0,
+        }
+    }
+
+    /// Converts a score into a result, where a positive score is the number
+    /// of stones the player to act will have left when they win and a
+    /// negative one the same for the opponent.
+    fn solution(&self, score: i32) -> Solution {
+        let size = self.size() as i32;
+        let n = self.moves as i32;
+        if score > 0 {
+            let stones = (size + 1 - n) / 2 - score + 1;
+            Solution::Win((2 * stones - 1) as usize)
+        } else if score < 0 {
+            let stones = (size - n) / 2 + score + 1;
+            Solution::Loss((2 * stones) as usize)
+        } else {
+            Solution::Draw((size - n) as usize)
+        }
+    }
+}
+
+pub struct SolverParams {
+    // Number of transposition table entries.
+    pub table_size: usize,
+}
+
+/// Exact Connect Four solver.  Null window negamax over bitboards, trying
+/// moves that make the most threats first and otherwise from the center out.
+pub struct Solver {
+    table: TranspositionTable<usize>,
+    dimensions: (usize, usize, usize),
+    order: Vec<usize>,
+    nodes: usize,
+}
+
+impl Solver {
+    /// Nodes searched since the solver was created.
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+
+    fn prepare(&mut self, game: &ConnectFour) {
+        let dimensions = (game.width(), game.height(), game.needed());
+        if dimensions != self.dimensions {
+            let width = game.width();
+            self.table.clear();
+            self.dimensions = dimensions;
+            self.order = (0..width)
+                .map(|i| {
+                    let offset = (i + 1) / 2;
+                    if i % 2 == 0 {
+                        width / 2 + offset
+                    } else {
+                        width / 2 - offset
+                    }
+                })
+                .collect();
+        }
+    }
+
+    // Requires that the player to act can't win with their next move.
+    fn negamax(&mut self, pos: &Position, mut alpha: i32, mut beta: i32) -> i32 {
+        self.nodes += 1;
+        let size = pos.size() as i32;
+        let n = pos.moves as i32;
+
+        let next = pos.non_losing_moves();
+        if next == 0 {
+            return -(size - n) / 2;
+        }
+
+        if n >= size - 2 {
+            return 0;
+        }
+
+        let min = -(size - 2 - n) / 2;
+        if alpha < min {
+            alpha = min;
+            if alpha >= beta {
+                return alpha;
+            }
+        }
+
+        let max = (size - 1 - n) / 2;
+        if beta > max {
+            beta = max;
+            if alpha >= beta {
+                return beta;
+            }
+        }
+
+        let key = pos.key();
+        if let Some(entry) = self.table.get(key) {
+            match entry.bound {
+                Bound::Upper => beta = cmp::min(beta, entry.score),
+                Bound::Lower => alpha = cmp::max(alpha, entry.score),
+                Bound::Exact => return entry.score,
+            }
+            if alpha >= beta {
+                return entry.score;
+            }
+        }
+
+        // Insertion sort by score, keeping center first order among ties.
+        let mut moves = [(0, 0); 64];
+        let mut count = 0;
+        for &j in &self.order {
+            let m = next & pos.column_mask(j);
+            if m == 0 {
+                continue;
+            }
+            let score = pos.move_score(m);
+            let mut i = count;
+            while i > 0 && moves[i - 1].1 < score {
+                moves[i] = moves[i - 1];
+                i -= 1;
+            }
+            moves[i] = (m, score);
+            count += 1;
+        }
+
+        for &(m, _) in &moves[..count] {
+            let mut child = *pos;
+            child.play(m);
+            let score = -self.negamax(&child, -beta, -alpha);
+            if score >= beta {
+                self.table.insert(key, 0, score, Bound::Lower, None);
+                return score;
+            }
+            if score > alpha {
+                alpha = score;
+            }
+        }
+
+        self.table.insert(key, 0, alpha, Bound::Upper, None);
+        alpha
+    }
+
+    fn score(&mut self, pos: &Position) -> i32 {
+        let size = pos.size() as i32;
+        let n = pos.moves as i32;
+        if pos.can_win_next() {
+            return (size + 1 - n) / 2;
+        }
+
+        // Narrow down on the score with null window searches.
+        let mut min = -(size - n) / 2;
+        let mut max = (size + 1 - n) / 2;
+        while min < max {
+            let mut med = min + (max - min) / 2;
+            if med <= 0 && min / 2 < med {
+                med = min / 2;
+            } else if med >= 0 && max / 2 > med {
+                med = max / 2;
+            }
+
+            let r = self.negamax(pos, med, med + 1);
+            if r <= med {
+                max = r;
+            } else {
+                min = r;
+            }
+        }
+        min
+    }
+
+    pub fn solve(&mut self, game: &ConnectFour) -> Solution {
+        if game.has_winner() {
+            return Solution::Loss(0);
+        }
+
+        self.prepare(game);
+        let pos = Position::from_game(game);
+        if pos.moves == pos.size() {
+            return Solution::Draw(0);
+        }
+
+        let score = self.score(&pos);
+        pos.solution(score)
+    }
+
+    /// Solves every legal move, from the point of view of the player making
+    /// it.
+    pub fn solve_moves(&mut self, game: &ConnectFour) -> Vec<(<ConnectFour as Game>::Move, Solution)> {
+        game.possible_moves()
+            .into_iter()
+            .map(|m| {
+                let mv = *m.valid_move();
+                (mv, self.solve(&m.apply()).before_move())
+            })
+            .collect()
+    }
+
+    /// A best move from `pos`, whose score is `score`, that needs no search:
+    /// a win on the spot, the only move that doesn't lose at once, or one the
+    /// table proves holds the opponent to `-score`.  `None` if the table
+    /// doesn't know.
+    fn known_best(&self, pos: &Position, score: i32) -> Option<u64> {
+        let possible = pos.possible();
+        let moves: Vec<u64> = self.order
+            .iter()
+            .map(|&j| possible & pos.column_mask(j))
+            .filter(|&m| m != 0)
+            .collect();
+        let wins = pos.winning_cells(pos.current);
+        if let Some(&m) = moves.iter().find(|&&m| m & wins != 0) {
+            return Some(m);
+        }
+
+        let next = pos.non_losing_moves();
+        let (size, n) = (pos.size() as i32, pos.moves as i32);
+        if next == 0 || score == -(size - n) / 2 {
+            // Everything loses as soon as it can, so any move holds out as
+            // long as the best.
+            return moves.first().cloned();
+        }
+        if next & (next - 1) == 0 || n >= size - 2 {
+            // A forced move, or the last couple of cells, which can only be
+            // drawn.
+            return moves.into_iter().find(|&m| m & next != 0);
+        }
+        moves.into_iter().filter(|&m| m & next != 0).find(|&m| {
+            let mut child = *pos;
+            child.play(m);
+            self.table
+                .get(child.key())
+                .map(|e| e.bound == Bound::Upper && e.score <= -score)
+                .unwrap_or(false)
+        })
+    }
+
+    /// Perfect play from both sides after `best`, which leads to `solution`,
+    /// for as long as the table from solving the moves knows it.
+    fn principal_variation(
+        &self,
+        game: &ConnectFour,
+        best: <ConnectFour as Game>::Move,
+        solution: Solution,
+    ) -> Vec<<ConnectFour as Game>::Move> {
+        let mut pv = vec![best];
+        let mut line = game.clone();
+        line.apply(best);
+        let mut pos = Position::from_game(&line);
+        let mut score = pos.score(solution.after_move());
+        while !line.is_over() {
+            let m = match self.known_best(&pos, score) {
+                Some(m) => m,
+                None => break,
+            };
+            let mv = (pos.column(m), line.to_act());
+            pv.push(mv);
+            line.apply(mv);
+            pos.play(m);
+            score = -score;
+        }
+        pv
+    }
+
+    /// Solves every legal move, best first, preferring the center among
+    /// equally good moves.
+    fn rank_moves(&mut self, game: &ConnectFour) -> Vec<(<ConnectFour as Game>::Move, Solution)> {
+        self.prepare(game);
+        let mut moves = self.solve_moves(game);
+        let order = self.order.clone();
+        moves.sort_by_key(|&((j, _), _)| order.iter().position(|&k| k == j));
+        // Stable, so the center still comes first among equals.
+        moves.sort_by(|a, b| b.1.rank().cmp(&a.1.rank()));
+        moves
+    }
+}
+
+impl Strategy<ConnectFour> for Solver {
+    type Params = SolverParams;
+
+    fn decide(&mut self, game: &ConnectFour) -> <ConnectFour as Game>::Move {
+        let moves = self.rank_moves(game);
+        moves.first().expect("No moves available from start position.").0
+    }
+
+    fn analyze(&mut self, game: &ConnectFour) -> Analysis<ConnectFour> {
+        let start = Instant::now();
+        let nodes = self.nodes;
+        let ranked = self.rank_moves(game);
+        let pv = match ranked.first() {
+            Some(&(m, solution)) => self.principal_variation(game, m, solution),
+            None => Vec::new(),
+        };
+        let moves = ranked
+            .into_iter()
+            .map(|(m, solution)| {
+                RatedMove {
+                    m: m,
+                    visits: 0,
+                    rating: Some(Rating::Proven(solution.value())),
+                }
+            })
+            .collect();
+
+        Analysis {
+            moves: moves,
+            pv: pv,
+            nodes: self.nodes - nodes,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    fn create(params: SolverParams) -> Self {
+        Solver {
+            table: TranspositionTable::new(params.table_size),
+            dimensions: (0, 0, 0),
+            order: Vec::new(),
+            nodes: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use game::connectfour::Color::*;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use strategies::mcts_core::test::play_moves;
+
+    fn solver() -> Solver {
+        Solver::create(SolverParams { table_size: 1 << 16 })
+    }
+
+    // Plain minimax over the game itself.
+    fn brute_force(game: &ConnectFour) -> Solution {
+        if game.has_winner() {
+            return Solution::Loss(0);
+        }
+        game.possible_moves()
+            .into_iter()
+            .map(|m| brute_force(&m.apply()).before_move())
+            .max_by_key(|s| s.rank())
+            .unwrap_or(Solution::Draw(0))
+    }
+
+    #[test]
+    fn test_wins_and_blocks() {
+        let game = play_moves(&ConnectFour::with_dimensions(&R, 5, 4, 4), &[2, 3, 2, 3, 2]);
+        // B has to block or lose straight away.
+        let mut s = solver();
+        assert_eq!(s.decide(&game), (2, B));
+
+        let game = play_moves(&game, &[0]);
+        assert_eq!(s.solve(&game), Solution::Win(1));
+        assert_eq!(s.decide(&game), (2, R));
+    }
+
+    #[test]
+    fn test_analysis_ranks_moves() {
+        let mut game = play_moves(&ConnectFour::with_dimensions(&R, 5, 4, 4), &[2, 3, 2, 3, 2]);
+        let analysis = solver().analyze(&game);
+        assert_eq!(analysis.best(), Some((2, B)));
+        assert_eq!(analysis.moves.len(), 5);
+        // Anything but blocking loses at once.
+        assert!(analysis.moves[1..].iter().all(|r| r.rating == Some(Rating::Proven(Value::Loss))));
+        assert!(analysis.nodes > 0);
+
+        // The line of play goes on past the block, as far as solving the
+        // moves proved it.
+        assert_eq!(analysis.pv[0], (2, B));
+        assert!(analysis.pv.len() > 1);
+        let mut check = solver();
+        let mut expected = check.solve(&game);
+        for &m in &analysis.pv {
+            assert!(game.try_move(m));
+            // Every move in the line keeps the result, so it's a best move.
+            let solution = check.solve(&game);
+            assert_eq!(solution, expected.after_move());
+            expected = solution;
+        }
+    }
+
+    #[test]
+    fn test_matches_brute_force() {
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1, 2, 3, 4]);
+        let mut s = solver();
+        let mut checked = 0;
+        while checked < 6 {
+            let mut game = ConnectFour::with_dimensions(&R, 5, 4, 4);
+            for _ in 0..12 {
+                let j = rng.gen_range(0, 5);
+                let to_act = game.to_act();
+                game.try_move((j, to_act));
+            }
+            if game.has_winner() {
+                continue;
+            }
+            assert_eq!(s.solve(&game), brute_force(&game), "{}", game);
+            checked += 1;
+        }
+    }
+}
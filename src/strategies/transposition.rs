@@ -0,0 +1,127 @@
+use game::Score;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// How a stored score relates to the true value of the position.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    // The search failed high, the true value is at least `score`.
+    Lower,
+    // The search failed low, the true value is at most `score`.
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Entry<M> {
+    pub key: u64,
+    pub depth: usize,
+    pub score: Score,
+    pub bound: Bound,
+    pub best_move: Option<M>,
+    generation: u32,
+}
+
+/// Fixed size table of search results keyed by position hash.  Each key maps
+/// to a single slot; a new result replaces the old one if the old one is from
+/// an earlier search or was searched no deeper.
+pub struct TranspositionTable<M> {
+    entries: Vec<Option<Entry<M>>>,
+    generation: u32,
+}
+
+impl<M: Copy> TranspositionTable<M> {
+    pub fn new(size: usize) -> Self {
+        TranspositionTable {
+            entries: vec![None; size],
+            generation: 0,
+        }
+    }
+
+    pub fn key<G: Hash>(game: &G) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        game.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn slot(&self, key: u64) -> Option<usize> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        Some((key % self.entries.len() as u64) as usize)
+    }
+
+    pub fn get(&self, key: u64) -> Option<&Entry<M>> {
+        self.slot(key)
+            .and_then(|i| self.entries[i].as_ref())
+            .and_then(|e| if e.key == key { Some(e) } else { None })
+    }
+
+    pub fn insert(
+        &mut self,
+        key: u64,
+        depth: usize,
+        score: Score,
+        bound: Bound,
+        best_move: Option<M>,
+    ) {
+        let i = match self.slot(key) {
+            Some(i) => i,
+            None => return,
+        };
+
+        let generation = self.generation;
+        let replace = match self.entries[i] {
+            None => true,
+            Some(ref e) => e.generation != generation || e.key == key || depth >= e.depth,
+        };
+
+        if replace {
+            self.entries[i] = Some(Entry {
+                key: key,
+                depth: depth,
+                score: score,
+                bound: bound,
+                best_move: best_move,
+                generation: generation,
+            });
+        }
+    }
+
+    /// Marks everything currently stored as left over from a previous search,
+    /// so it will be replaced before anything from the new one.
+    pub fn new_search(&mut self) {
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.iter().filter(|e| e.is_some()).count()
+    }
+
+    pub fn clear(&mut self) {
+        for e in self.entries.iter_mut() {
+            *e = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_replacement() {
+        let mut tt = TranspositionTable::<usize>::new(4);
+        tt.insert(1, 5, 10, Bound::Exact, Some(0));
+        // Same slot, shallower, same search: kept out.
+        tt.insert(5, 2, 20, Bound::Exact, Some(1));
+        assert_eq!(tt.get(1).map(|e| e.score), Some(10));
+        assert!(tt.get(5).is_none());
+
+        // Anything from a new search wins the slot.
+        tt.new_search();
+        tt.insert(5, 2, 20, Bound::Lower, Some(1));
+        assert!(tt.get(1).is_none());
+        assert_eq!(tt.get(5).map(|e| e.bound), Some(Bound::Lower));
+    }
+}